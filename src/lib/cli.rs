@@ -1,8 +1,12 @@
-use super::client;
-use super::config::Config;
-use super::daemons;
+use super::client::{self, Frame, Location, MissingFiles};
+use super::config::{Config, Overrides};
+use super::error::{Error, Result};
+use super::format::{self, DaemonRecord, Format};
+use super::daemons::{self, Health, LaunchOptions, Shutdown, TcpOptions};
+use super::project::{self, ProjectEnv};
 use clap::{Parser, Subcommand};
-use standard_styled::standard_styled;
+use standard_styled::{standard_styled, CommandParts};
+use std::io::Read;
 use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
@@ -12,6 +16,43 @@ use std::path::PathBuf;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// read configuration from PATH, instead of `$XDG_CONFIG_HOME/eud/config.toml`
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Emacs executable used to launch daemons
+    #[arg(long, global = true, value_name = "PATH")]
+    emacs_exec: Option<String>,
+
+    /// emacsclient executable used to open frames in the terminal (`--tty`)
+    #[arg(long, global = true, value_name = "PATH")]
+    emacs_client_exec: Option<String>,
+
+    /// socket name used when NAME is not given to `new`
+    #[arg(long, global = true, value_name = "NAME")]
+    default_socket: Option<String>,
+
+    /// directory in which daemon socket files are kept
+    #[arg(long, global = true, value_name = "DIR")]
+    server_socket_dir: Option<PathBuf>,
+
+    /// resolve the socket directory as Emacs does (`$XDG_RUNTIME_DIR/emacs`,
+    /// `$TMPDIR/emacs$UID` or `/tmp/emacs$UID`), unless set explicitly
+    #[arg(long, global = true)]
+    emacs_compatible: bool,
+}
+
+impl Cli {
+    fn overrides(&self) -> Overrides {
+        Overrides {
+            emacs_exec: self.emacs_exec.clone(),
+            emacs_client_exec: self.emacs_client_exec.clone(),
+            default_socket: self.default_socket.clone(),
+            server_socket_dir: self.server_socket_dir.clone(),
+            emacs_compatible: self.emacs_compatible.then_some(true),
+        }
+    }
 }
 
 #[derive(Debug, Subcommand)]
@@ -22,31 +63,277 @@ enum Commands {
         /// list active daemons's names only, one per line
         #[arg(short = '1', default_value_t = false)]
         short: bool,
+        /// also measure each daemon's CPU usage and find its Emacs version;
+        /// without --format or --template, list each daemon's uptime,
+        /// memory (RSS), CPU usage, version, and working directory
+        #[arg(short = 'l', long, default_value_t = false, conflicts_with = "short")]
+        long: bool,
+        /// list every user's daemons, not only your own
+        #[arg(long, default_value_t = false)]
+        all_users: bool,
+        /// list every field of each daemon in a machine-readable format
+        #[arg(long, value_enum, conflicts_with_all = ["short", "template"])]
+        format: Option<Format>,
+        /// list each daemon with TEMPLATE, replacing each `{field}` (e.g.
+        /// `{name}\t{pid}\t{socket}`); fields are name, pid, uid, socket,
+        /// start_time, uptime, rss, cpu, cwd, exe and version
+        #[arg(long, conflicts_with = "short")]
+        template: Option<String>,
+        /// also list each daemon's environment (which may hold secrets) as
+        /// the `env` field of --format or --template, a JSON object
+        #[arg(long, default_value_t = false, conflicts_with = "short")]
+        with_env: bool,
+        /// omit the header row from `--format tsv|csv`
+        #[arg(long, default_value_t = false)]
+        no_header: bool,
     },
 
-    /// launch new daemon
+    /// launch new daemon, with the options of its profile (if configured)
+    /// followed by any given here
     #[command()]
-    New { name: Option<String> },
+    New {
+        name: Option<String>,
+        /// seconds to wait for the daemon to be ready for clients
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+        /// use DIR in place of `~/.emacs.d` (Emacs 29 or later)
+        #[arg(long, value_name = "DIR")]
+        init_directory: Option<PathBuf>,
+        /// load no init files (Emacs' `-Q`)
+        #[arg(short = 'Q', long, default_value_t = false)]
+        no_init: bool,
+        /// load the elisp FILE (may be repeated)
+        #[arg(long, value_name = "FILE")]
+        load: Vec<PathBuf>,
+        /// evaluate the elisp EXPR, after loading files (may be repeated)
+        #[arg(long, value_name = "EXPR")]
+        eval: Vec<String>,
+        /// run the daemon in DIR
+        #[arg(long, value_name = "DIR")]
+        cwd: Option<PathBuf>,
+        /// set the environment variable KEY to VAL for the daemon (may be
+        /// repeated)
+        #[arg(long, value_name = "KEY=VAL", value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+        /// listen on TCP, rather than a local socket, writing the server's
+        /// address and key to a file in `server_auth_dir`
+        #[arg(long, default_value_t = false)]
+        tcp: bool,
+        /// the host on which to listen (by default, 127.0.0.1)
+        #[arg(long, value_name = "HOST", requires = "tcp")]
+        host: Option<String>,
+        /// the port on which to listen (by default, any free port)
+        #[arg(long, value_name = "PORT", requires = "tcp")]
+        port: Option<u16>,
+        /// further arguments passed to Emacs
+        #[arg(last = true, value_name = "EMACS_ARGS")]
+        emacs_args: Vec<String>,
+    },
+
+    /// list the daemon profiles in the configuration file, and whether each
+    /// daemon is running
+    #[command()]
+    Profiles,
+
+    /// launch every daemon whose profile sets `autostart`, unless running
+    #[command()]
+    Autostart {
+        /// seconds to wait for each daemon to be ready for clients
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+    },
 
-    /// kill daemon with socket NAME, or kill all active daemons with --all
+    /// kill daemon with socket NAME, or kill all active daemons with --all;
+    /// asks the daemon to save its buffers and exit, then escalates to the
+    /// TERM, then KILL, signals
     #[command(arg_required_else_help = true)]
     Kill {
         #[arg(long = "all", default_value_t = false)]
         all: bool,
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        daemon_name: Option<String>,
+        /// seconds to wait for the daemon to exit before escalating
+        #[arg(long, default_value_t = 10)]
+        grace: u64,
+        /// stop the daemon only in this way, without escalating
+        #[arg(long, value_enum)]
+        signal: Option<Shutdown>,
+        /// skip asking the daemon to save and exit (i.e. start with TERM)
+        #[arg(short, long, default_value_t = false, conflicts_with = "signal")]
+        force: bool,
+    },
+
+    /// restart daemon with socket NAME (or every daemon, one at a time, with
+    /// --all), with the arguments, working directory and environment with
+    /// which it was launched
+    #[command(arg_required_else_help = true)]
+    Restart {
+        #[arg(long = "all", default_value_t = false)]
+        all: bool,
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
         daemon_name: Option<String>,
+        /// seconds to wait for the daemon to exit before escalating
+        #[arg(long, default_value_t = 10)]
+        grace: u64,
+        /// skip asking the daemon to save and exit (i.e. start with TERM)
+        #[arg(short, long, default_value_t = false)]
+        force: bool,
+        /// seconds to wait for the relaunched daemon to be ready for clients
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+    },
+
+    /// remove stale socket and server files (on which nothing is listening),
+    /// and report daemons which have no socket
+    #[command()]
+    Gc {
+        /// only report what would be removed
+        #[arg(short = 'n', long, default_value_t = false)]
+        dry_run: bool,
+        /// kill daemons which have no socket
+        #[arg(long, default_value_t = false)]
+        kill_orphans: bool,
+        /// ask daemons which have no socket to recreate it with
+        /// `server-start` (by sending the USR1 signal, which Emacs must
+        /// bind, e.g. `(define-key special-event-map [sigusr1] #'server-start)`)
+        #[arg(long, default_value_t = false, conflicts_with = "kill_orphans")]
+        restart_servers: bool,
     },
 
-    /// connect Emacs client to daemon; visits path at FILE
+    /// connect Emacs client to daemon; visits each FILE (a directory in
+    /// `dired`), or the current directory
     #[command(arg_required_else_help = true)]
     Connect {
         #[arg(required = true)]
         daemon: String,
-        #[arg(required = false)]
-        file: Option<PathBuf>,
-        /// whether to send stdout/stderr back to the terminal
-        /// note: if true, this will leave `eud connect` as a background job
+        /// a file to visit, as PATH, PATH:LINE or PATH:LINE:COLUMN, or
+        /// preceded by +LINE or +LINE:COLUMN
+        #[arg(required = false, value_name = "FILE")]
+        files: Vec<String>,
+        /// what to do with a FILE which does not exist (overrides
+        /// `missing_files` in the configuration)
+        #[arg(long, value_enum, value_name = "POLICY")]
+        missing: Option<MissingFiles>,
+        /// print anything the daemon sends back to the client
         #[arg(required = false, short = 'z', default_value_t = false)]
         output_here: bool,
+        /// wait until the files are finished with (`server-edit`, C-x #)
+        /// and exit unsuccessfully if they are abandoned (`server-edit-abort`)
+        #[arg(short = 'w', long, default_value_t = false, conflicts_with = "output_here")]
+        wait: bool,
+        /// open a frame in this terminal, rather than a graphical frame
+        #[arg(short = 't', long = "tty", visible_alias = "nw", default_value_t = false)]
+        tty: bool,
+        /// visit FILE in the daemon's current frame, rather than a new one
+        #[arg(short = 'r', long, default_value_t = false, conflicts_with = "tty")]
+        reuse_frame: bool,
+        /// launch the daemon (as `eud new` does) if it is not running
+        #[arg(short = 's', long, default_value_t = false)]
+        start: bool,
+        /// do not launch the daemon, even if `start_on_connect` is set
+        #[arg(long, default_value_t = false, conflicts_with = "start")]
+        no_start: bool,
+        /// seconds to wait for a launched daemon to be ready for clients
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+    },
+
+    /// edit each FILE with the default daemon (or NAME), waiting until
+    /// they are finished with, as `$EDITOR` (e.g. `EDITOR="eud edit"`)
+    #[command(arg_required_else_help = true)]
+    Edit {
+        /// a file to visit, as for `connect`; one which does not exist is
+        /// visited as a new file
+        #[arg(required = true, value_name = "FILE")]
+        files: Vec<String>,
+        /// edit with the daemon named NAME, rather than the default daemon
+        #[arg(short = 'd', long, value_name = "NAME")]
+        daemon: Option<String>,
+        /// open a frame in this terminal, rather than a graphical frame
+        #[arg(short = 't', long = "tty", visible_alias = "nw", default_value_t = false)]
+        tty: bool,
+        /// visit FILE in the daemon's current frame, rather than a new one
+        #[arg(short = 'r', long, default_value_t = false, conflicts_with = "tty")]
+        reuse_frame: bool,
+        /// launch the daemon (as `eud new` does) if it is not running
+        #[arg(short = 's', long, default_value_t = false)]
+        start: bool,
+        /// do not launch the daemon, even if `start_on_connect` is set
+        #[arg(long, default_value_t = false, conflicts_with = "start")]
+        no_start: bool,
+        /// seconds to wait for a launched daemon to be ready for clients
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+    },
+
+    /// insert stdin into a buffer of the default daemon (or NAME), as it
+    /// is read, and show the buffer in a new frame, e.g. `make | eud pipe`
+    #[command()]
+    Pipe {
+        daemon: Option<String>,
+        /// the buffer to insert into, which is created if need be
+        #[arg(short = 'b', long, default_value = "*eud-pipe*", value_name = "BUF")]
+        buffer: String,
+        /// enable the major MODE in the buffer, e.g. `compilation-mode`
+        #[arg(short = 'm', long, value_name = "MODE")]
+        mode: Option<String>,
+        /// keep the end of the buffer in view as input arrives, as `tail -f`
+        #[arg(short = 'f', long, default_value_t = false)]
+        follow: bool,
+        /// show the buffer in the daemon's current frame, rather than a new one
+        #[arg(short = 'r', long, default_value_t = false)]
+        reuse_frame: bool,
+        /// do not show the buffer
+        #[arg(long, default_value_t = false, conflicts_with = "reuse_frame")]
+        no_frame: bool,
+    },
+
+    /// connect to the daemon of the project containing DIR (or the current
+    /// directory), launching it in the project's root if it is not running
+    #[command()]
+    Project {
+        dir: Option<PathBuf>,
+        /// visit FILE, rather than the project's root
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// open a frame in this terminal, rather than a graphical frame
+        #[arg(short = 't', long = "tty", visible_alias = "nw", default_value_t = false)]
+        tty: bool,
+        /// visit FILE in the daemon's current frame, rather than a new one
+        #[arg(short = 'r', long, default_value_t = false, conflicts_with = "tty")]
+        reuse_frame: bool,
+        /// launch the daemon in the project's `nix develop` or `direnv`
+        /// environment (overrides `project_env` in the configuration)
+        #[arg(long, value_enum, value_name = "ENV")]
+        project_env: Option<ProjectEnv>,
+        /// seconds to wait for a new daemon to be ready for clients
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+    },
+
+    /// evaluate elisp EXPR in daemon NAME, and print the result
+    #[command(arg_required_else_help = true)]
+    Eval {
+        #[arg(required = true)]
+        daemon: String,
+        /// expression to evaluate (multiple words are joined with spaces);
+        /// read from stdin if omitted or `-`
+        expr: Vec<String>,
+        /// read the expression from FILE
+        #[arg(short = 'f', long, conflicts_with = "expr")]
+        file: Option<PathBuf>,
+        /// print the daemon, its pid, and the result or error as JSON
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+
+    /// check whether daemon NAME (or every daemon) is ready for clients
+    #[command()]
+    Status {
+        daemon: Option<String>,
+        /// seconds to wait for each daemon to answer
+        #[arg(long, default_value_t = 2)]
+        timeout: u64,
     },
 
     /// print directory location for daemon socket files
@@ -55,83 +342,279 @@ enum Commands {
     ServerSocketDirPath,
 }
 
-pub fn cli(config: &Config) -> Result<(), std::io::Error> {
-    match &Cli::parse().command {
-        Commands::List { short } => match short {
-            true => list_daemons_short(),
-            false => list_daemons(config)?,
+pub fn cli() -> Result<()> {
+    let args = Cli::parse();
+    let config = &Config::load(args.config.as_deref(), &args.overrides())?;
+    match &args.command {
+        Commands::List {
+            short,
+            long,
+            all_users,
+            format,
+            template,
+            with_env,
+            no_header,
+        } => match (short, format, template) {
+            (true, _, _) => list_daemons_short(*all_users, config),
+            (false, Some(format), _) => {
+                let records = daemon_records(*long, *all_users, *with_env, config);
+                let fields = format::fields(*with_env);
+                print!("{}", format::render(&records, &fields, *format, !no_header)?);
+            }
+            (false, None, Some(template)) => {
+                for record in daemon_records(*long, *all_users, *with_env, config) {
+                    println!("{}", format::render_template(template, &record)?);
+                }
+            }
+            (false, None, None) if *long => list_daemons_long(*all_users, config),
+            (false, None, None) => list_daemons(*all_users, config)?,
         },
-        Commands::New { name } => {
+        Commands::New {
+            name,
+            timeout,
+            init_directory,
+            no_init,
+            load,
+            eval,
+            cwd,
+            env,
+            tcp,
+            host,
+            port,
+            emacs_args,
+        } => {
+            // paths are relative to the current directory, rather than the
+            // daemon's
+            let absolute = |path: &PathBuf| std::path::absolute(path);
+            let options = LaunchOptions {
+                emacs: args.emacs_exec.clone(),
+                init_directory: init_directory.as_ref().map(absolute).transpose()?,
+                no_init: *no_init,
+                load: load.iter().map(absolute).collect::<std::io::Result<_>>()?,
+                eval: eval.clone(),
+                cwd: cwd.as_ref().map(absolute).transpose()?,
+                env: env.clone(),
+                args: emacs_args.clone(),
+                wrapper: vec![],
+                tcp: tcp.then(|| TcpOptions { host: host.clone(), port: *port }),
+            };
             let name_or_default = name.clone().unwrap_or(config.default_socket_name().clone());
-            // first check if a daemon with the same socket name (or the
-            // default name) already exists (whether in `eud's
-            // `server_socket_dir` location or otherwise)
-            match daemons::active_daemons_names().contains(&name_or_default) {
-                true => {
-                    let msg = concat!(
-                        "A daemon with name '{name_or_default}' is already running. ",
-                        "If you wish to connect to it, try:\n",
-                        "    `eud connect {name_or_default} [FILE]`",
-                    );
-                    println!("{}", msg);
+            new_daemon(
+                &name_or_default,
+                options,
+                std::time::Duration::from_secs(*timeout),
+                config,
+            )?
+        }
+        Commands::Profiles => list_profiles(config),
+        Commands::Autostart { timeout } => {
+            // attempt to launch every daemon, reporting each failure, but
+            // exit according to the last
+            let running = daemons::active_daemons_names(config);
+            let mut failure = None;
+            for (name, _) in config.profiles().iter().filter(|(_, profile)| profile.autostart) {
+                if running.contains(name) {
+                    println!("Emacs daemon '{name}' is already running");
+                    continue;
+                }
+                let timeout = std::time::Duration::from_secs(*timeout);
+                let launched = new_daemon(name, LaunchOptions::default(), timeout, config);
+                if let Err(e) = launched {
+                    if let Some(previous) = failure.replace(e) {
+                        eprintln!("Error trying to launch Emacs daemon:\n{previous}");
+                    }
                 }
-                false => tokio::runtime::Builder::new_multi_thread()
-                    .enable_all()
-                    .build()?
-                    .block_on(async {
-                        let name = name.clone();
-                        let cmd = daemons::build_new(name, config);
-                        match standard_styled(cmd, config.style()).await {
-                            Ok(_) => (),
-                            Err(e) => eprintln!("Tokio error from `standard_styled: {e}"),
-                        }
-                    }),
+            }
+            if let Some(e) = failure {
+                return Err(e);
             }
         }
-        Commands::Kill { all, daemon_name } => {
+        Commands::Kill {
+            all,
+            daemon_name,
+            grace,
+            signal,
+            force,
+        } => {
+            let stages = shutdown_stages(*signal, *force);
+            let grace = std::time::Duration::from_secs(*grace);
             if *all {
-                for result in daemons::kill_all() {
+                // attempt to kill every daemon, reporting each failure, but
+                // exit according to the last
+                let mut failure = None;
+                for result in daemons::kill_all(&stages, grace, config) {
                     match result {
-                        Ok(pid) => println!("Killed Emacs daemon with Pid {pid}"),
-                        Err(e) => eprintln!("Error trying to kill Emacs daemon process:\n{e}"),
+                        Ok((pid, stage)) => println!("Killed Emacs daemon with Pid {pid} ({stage})"),
+                        Err(e) => {
+                            if let Some(previous) = failure.replace(e) {
+                                eprintln!("Error trying to kill Emacs daemon process:\n{previous}");
+                            }
+                        }
                     }
                 }
-            } else if let Some(name) = daemon_name {
-                match daemons::kill_by_name(name) {
-                    Ok(pid) => println!("Killed Emacs daemon '{name}' [Pid: {pid} ]"),
-                    Err(e) => {
-                        eprintln!("{}", e);
-                        list_daemons(config)?;
-                    }
+                if let Some(e) = failure {
+                    return Err(e);
                 }
+            } else if let Some(name) = daemon_name {
+                let (pid, stage) = daemons::kill_by_name(name, &stages, grace, config)?;
+                println!("Killed Emacs daemon '{name}' [Pid: {pid} ] ({stage})");
             }
         }
+        Commands::Restart {
+            all,
+            daemon_name,
+            grace,
+            force,
+            timeout,
+        } => {
+            let daemons = match daemon_name {
+                Some(name) if !all => vec![daemons::find_by_name(name, config)?],
+                _ => daemons::get_all(config),
+            };
+            let stages = shutdown_stages(None, *force);
+            for daemon in daemons {
+                restart_daemon(
+                    &daemon,
+                    &stages,
+                    std::time::Duration::from_secs(*grace),
+                    std::time::Duration::from_secs(*timeout),
+                    config,
+                )?;
+            }
+        }
+        Commands::Gc {
+            dry_run,
+            kill_orphans,
+            restart_servers,
+        } => collect_garbage(*dry_run, *kill_orphans, *restart_servers, config)?,
         Commands::Connect {
             daemon,
-            file,
+            files,
+            missing,
             output_here,
+            wait,
+            tty,
+            reuse_frame,
+            start,
+            no_start,
+            timeout,
         } => {
-            let visit_file = file.clone().unwrap_or(std::env::current_dir()?);
-            match client::connect(daemon, visit_file, *output_here, config) {
-                Ok(client) => {
-                    println!("Launching Emacs client connected to '{}' .", daemon);
-                    match output_here {
-                        false => (),
-                        true => {
-                            print!("...");
-                            let output = client.wait_with_output().expect("what? no outputs??");
-                            println!(
-                                "stdout:\n{}\n",
-                                String::from_utf8_lossy(output.stdout.as_slice())
-                            );
-                            eprintln!(
-                                "stderr:\n{}",
-                                String::from_utf8_lossy(output.stderr.as_slice())
-                            );
-                        }
-                    };
+            let visit_files = match files.is_empty() {
+                true => vec![Location::from(std::env::current_dir()?)],
+                false => Location::parse_args(files)?,
+            };
+            if starts_daemon(*start, *no_start, config) {
+                let timeout = std::time::Duration::from_secs(*timeout);
+                start_if_missing(daemon, LaunchOptions::default(), timeout, config)?;
+            }
+            let missing = missing.unwrap_or(config.missing_files());
+            let frame = frame(*tty, *reuse_frame);
+            match wait {
+                true => edit(daemon, visit_files, missing, frame, config)?,
+                false => connect_daemon(daemon, visit_files, missing, frame, *output_here, config)?,
+            }
+        }
+        Commands::Edit {
+            files,
+            daemon,
+            tty,
+            reuse_frame,
+            start,
+            no_start,
+            timeout,
+        } => {
+            let daemon = daemon.as_ref().unwrap_or(config.default_socket_name());
+            if starts_daemon(*start, *no_start, config) {
+                let timeout = std::time::Duration::from_secs(*timeout);
+                start_if_missing(daemon, LaunchOptions::default(), timeout, config)?;
+            }
+            let visit_files = Location::parse_args(files)?;
+            edit(daemon, visit_files, MissingFiles::Create, frame(*tty, *reuse_frame), config)?
+        }
+        Commands::Pipe {
+            daemon,
+            buffer,
+            mode,
+            follow,
+            reuse_frame,
+            no_frame,
+        } => {
+            let daemon = daemon.as_ref().unwrap_or(config.default_socket_name());
+            let target = client::PipeTarget {
+                buffer: buffer.clone(),
+                mode: mode.clone(),
+                follow: *follow,
+                show: (!no_frame).then(|| frame(false, *reuse_frame)),
+            };
+            client::pipe(daemon, &target, std::io::stdin().lock(), config)?
+        }
+        Commands::Project {
+            dir,
+            file,
+            tty,
+            reuse_frame,
+            project_env,
+            timeout,
+        } => {
+            let dir = std::path::absolute(dir.clone().unwrap_or(std::env::current_dir()?))?;
+            if !dir.is_dir() {
+                return Err(Error::FileNotFound { path: dir });
+            }
+            // a directory in no project is a project of its own
+            let root = project::find_root(&dir).unwrap_or(dir);
+            let name = project::socket_name(&root);
+            let options = LaunchOptions {
+                cwd: Some(root.clone()),
+                wrapper: project_env.unwrap_or(config.project_env()).wrapper(&root),
+                ..Default::default()
+            };
+            start_if_missing(&name, options, std::time::Duration::from_secs(*timeout), config)?;
+            let visit_file = file.clone().unwrap_or(root);
+            let visit_files = vec![visit_file.into()];
+            connect_daemon(&name, visit_files, config.missing_files(), frame(*tty, *reuse_frame), false, config)?
+        }
+        Commands::Eval {
+            daemon,
+            expr,
+            file,
+            json,
+        } => {
+            let expr = match (file, expr.as_slice()) {
+                (Some(file), _) => std::fs::read_to_string(file)?,
+                (None, []) => read_stdin()?,
+                (None, [dash]) if dash == "-" => read_stdin()?,
+                (None, words) => words.join(" "),
+            };
+            let evaluation = client::eval(daemon, &expr, config)?;
+            match json {
+                true => println!(
+                    "{}",
+                    serde_json::to_string(&evaluation).map_err(std::io::Error::from)?
+                ),
+                false => if let Some(result) = &evaluation.result {
+                    println!("{result}");
+                },
+            }
+            if let Some(message) = evaluation.error {
+                return Err(Error::Eval { daemon: evaluation.daemon, message });
+            }
+        }
+        Commands::Status { daemon, timeout } => {
+            let timeout = std::time::Duration::from_secs(*timeout);
+            let daemons = match daemon {
+                Some(name) => vec![daemons::find_by_name(name, config)?],
+                None => daemons::get_all(config),
+            };
+            let mut unhealthy = None;
+            for daemon in daemons {
+                let health = daemon.health(timeout);
+                println!("{:<14} {health}", daemon.socket_name);
+                if health != Health::Ready && unhealthy.is_none() {
+                    unhealthy = Some(Error::Unhealthy { name: daemon.socket_name, health });
                 }
-                Err(e) => eprint!("Error launching client:\n{e}"),
+            }
+            if let Some(e) = unhealthy {
+                return Err(e);
             }
         }
         Commands::ServerSocketDirPath => {
@@ -141,22 +624,339 @@ pub fn cli(config: &Config) -> Result<(), std::io::Error> {
     Ok(())
 }
 
-pub fn list_daemons(config: &Config) -> Result<(), std::io::Error> {
-    let extant_daemons = daemons::get_all();
+fn frame(tty: bool, reuse_frame: bool) -> Frame {
+    match (tty, reuse_frame) {
+        (true, _) => Frame::Tty,
+        (false, true) => Frame::Reuse,
+        (false, false) => Frame::Create,
+    }
+}
+
+/// Connect a client to the daemon named `daemon`, visiting `visit_files`
+/// (those which do not exist as `missing` determines), printing anything
+/// the daemon sends back if `output_here`.
+fn connect_daemon(
+    daemon: &str,
+    visit_files: Vec<Location>,
+    missing: MissingFiles,
+    frame: Frame,
+    output_here: bool,
+    config: &Config,
+) -> Result<()> {
+    let client = client::connect(daemon, visit_files, missing, frame, false, config)?;
+    if frame != Frame::Tty {
+        println!("Connecting Emacs client to '{}' .", daemon);
+    }
+    let response = client.finish()?;
+    if output_here && !response.values.is_empty() {
+        println!("{}", response.output());
+    }
+    Ok(())
+}
+
+/// Connect a client to the daemon named `daemon`, visiting `visit_files`,
+/// and wait until it has finished with them (i.e. until `server-edit`);
+/// fails if they are abandoned (i.e. on `server-edit-abort`).
+fn edit(
+    daemon: &str,
+    visit_files: Vec<Location>,
+    missing: MissingFiles,
+    frame: Frame,
+    config: &Config,
+) -> Result<()> {
+    client::connect(daemon, visit_files, missing, frame, true, config)?.finish()?;
+    Ok(())
+}
+
+/// Launch a new daemon named `name`, with the options of its profile (if
+/// it has one) followed by `options`, unless it is already running.
+fn new_daemon(
+    name: &str,
+    options: LaunchOptions,
+    timeout: std::time::Duration,
+    config: &Config,
+) -> Result<()> {
+    // first check if a daemon with the same socket name (or the default
+    // name) already exists (whether in `eud's `server_socket_dir` location
+    // or otherwise)
+    if daemons::active_daemons_names(config).iter().any(|running| running == name) {
+        return Err(Error::DaemonAlreadyRunning { name: name.to_string() });
+    }
+    let options = match config.profile(name) {
+        Some(profile) => LaunchOptions::from(profile).merge(options),
+        None => options,
+    };
+    if let Some(dir) = options.cwd.as_ref().filter(|dir| !dir.is_dir()) {
+        return Err(Error::FileNotFound { path: dir.clone() });
+    }
+    launch_daemon(
+        daemons::build_new(Some(name.to_string()), &options, config),
+        name,
+        timeout,
+        config,
+    )
+}
+
+/// Whether a client launches its daemon if it is not running: if asked to
+/// `start`, or if `start_on_connect` is set, unless asked to `no_start`.
+fn starts_daemon(start: bool, no_start: bool, config: &Config) -> bool {
+    start || (config.start_on_connect() && !no_start)
+}
+
+/// Launch the daemon named `name` as `new_daemon` does, unless it is
+/// already running.
+fn start_if_missing(
+    name: &str,
+    options: LaunchOptions,
+    timeout: std::time::Duration,
+    config: &Config,
+) -> Result<()> {
+    match daemons::active_daemons_names(config).iter().any(|running| running == name) {
+        true => Ok(()),
+        false => new_daemon(name, options, timeout, config),
+    }
+}
+
+/// Launch a daemon with `command`, then wait for its server to be ready
+/// for clients (for up to `timeout`).
+fn launch_daemon(
+    command: CommandParts,
+    name: &str,
+    timeout: std::time::Duration,
+    config: &Config,
+) -> Result<()> {
+    let completion = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(standard_styled(command, config.style()))?;
+    if !completion.success() {
+        config.style().finish(false);
+        return Err(Error::Launch {
+            name: name.to_string(),
+            status: Some(completion.status),
+            stderr_tail: completion.stderr_tail,
+        });
+    }
+    let health = daemons::wait_until_ready(name, timeout, config);
+    config.style().finish(health == Some(Health::Ready));
+    match health {
+        Some(Health::Ready) => Ok(()),
+        Some(health) => Err(Error::NotReady {
+            name: name.to_string(),
+            timeout,
+            health,
+        }),
+        None => Err(Error::Launch {
+            name: name.to_string(),
+            status: None,
+            stderr_tail: completion.stderr_tail,
+        }),
+    }
+}
+
+/// The stages by which `eud kill` (or `restart`) stops a daemon: only
+/// `signal`, if given, otherwise escalating from asking it to exit (or
+/// from TERM, if `force`).
+fn shutdown_stages(signal: Option<Shutdown>, force: bool) -> Vec<Shutdown> {
+    match (signal, force) {
+        (Some(stage), _) => vec![stage],
+        (None, true) => vec![Shutdown::Term, Shutdown::Kill],
+        (None, false) => Shutdown::ALL.to_vec(),
+    }
+}
+
+/// Stop `daemon`, then launch it again as it was launched, and wait for
+/// it to be ready for clients.
+fn restart_daemon(
+    daemon: &daemons::DaemonProcess,
+    stages: &[Shutdown],
+    grace: std::time::Duration,
+    timeout: std::time::Duration,
+    config: &Config,
+) -> Result<()> {
+    let command = daemons::build_restart(daemon, config);
+    let stage = daemon.shut_down(stages, grace)?;
+    println!("Stopped Emacs daemon '{}' [Pid: {} ] ({stage})", daemon.socket_name, daemon.pid);
+    daemons::await_socket_removal(daemon, grace)?;
+    launch_daemon(command, &daemon.socket_name, timeout, config)
+}
+
+/// Remove stale sockets, and report (or kill, or restart the server of)
+/// orphaned daemons.
+fn collect_garbage(
+    dry_run: bool,
+    kill_orphans: bool,
+    restart_servers: bool,
+    config: &Config,
+) -> Result<()> {
+    // find orphans before removing sockets, as their sockets may be stale
+    let orphans = daemons::orphaned(config);
+    for socket in daemons::stale_sockets(config) {
+        // a stale socket in a directory shared with other programs may not
+        // be Emacs', so is left for the user to judge
+        if !daemons::in_emacs_socket_dir(&socket, config) {
+            println!("Not removing stale socket {} (not in a directory used only by Emacs)", socket.display());
+            continue;
+        }
+        match dry_run {
+            true => println!("Would remove stale socket {}", socket.display()),
+            false => {
+                std::fs::remove_file(&socket)?;
+                println!("Removed stale socket {}", socket.display());
+            }
+        }
+    }
+    for file in daemons::stale_server_files(config) {
+        match dry_run {
+            true => println!("Would remove stale server file {}", file.display()),
+            false => {
+                std::fs::remove_file(&file)?;
+                println!("Removed stale server file {}", file.display());
+            }
+        }
+    }
+    for (daemon, health) in &orphans {
+        println!("Orphaned Emacs daemon '{}' [Pid: {} ] ({health})", daemon.socket_name, daemon.pid);
+        match (dry_run, kill_orphans, restart_servers) {
+            (true, true, _) => println!("Would kill Emacs daemon '{}'", daemon.socket_name),
+            (true, false, true) => println!("Would ask Emacs daemon '{}' to restart its server", daemon.socket_name),
+            (_, false, false) => (),
+            (false, true, _) => {
+                let stages = shutdown_stages(None, true);
+                let stage = daemon.shut_down(&stages, std::time::Duration::from_secs(10))?;
+                println!("Killed Emacs daemon '{}' [Pid: {} ] ({stage})", daemon.socket_name, daemon.pid);
+            }
+            (false, false, true) => {
+                daemon.restart_server()?;
+                println!("Asked Emacs daemon '{}' to restart its server", daemon.socket_name);
+            }
+        }
+    }
+    let acted = kill_orphans || restart_servers;
+    if !orphans.is_empty() && !acted {
+        println!("Kill these with `eud gc --kill-orphans`, or recreate their sockets with `eud gc --restart-servers`.");
+    }
+    Ok(())
+}
+
+/// Parse `KEY=VAL`, as given to `--env`.
+fn parse_env_var(arg: &str) -> std::result::Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VAL, found `{arg}`")),
+    }
+}
+
+fn read_stdin() -> std::io::Result<String> {
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input)?;
+    Ok(input)
+}
+
+pub fn list_daemons(all_users: bool, config: &Config) -> Result<()> {
+    let extant_daemons = match all_users {
+        true => daemons::get_all_users(config),
+        false => daemons::get_all(config),
+    };
     match extant_daemons.len() {
         0 => println!("No Emacs daemon processes are running."),
         _ => {
             println!("Current Emacs daemon instances:");
             extant_daemons.iter().for_each(|daemon| {
-                println!("{}", daemon.show(config));
+                println!("{}", daemon.show());
             });
         }
     }
     Ok(())
 }
 
-pub fn list_daemons_short() {
-    daemons::active_daemons_names()
-        .iter()
-        .for_each(|name| println!("{name}"))
+pub fn list_daemons_long(all_users: bool, config: &Config) {
+    let extant_daemons = daemons::get_all_measured(all_users, config);
+    match extant_daemons.len() {
+        0 => println!("No Emacs daemon processes are running."),
+        _ => {
+            println!("{}", daemons::long_header());
+            extant_daemons.iter().for_each(|daemon| {
+                println!("{}", daemon.show_long());
+            });
+        }
+    }
+}
+
+fn daemon_records(measured: bool, all_users: bool, with_env: bool, config: &Config) -> Vec<DaemonRecord> {
+    match (measured, all_users) {
+        (true, _) => daemons::get_all_measured(all_users, config),
+        (false, true) => daemons::get_all_users(config),
+        (false, false) => daemons::get_all(config),
+    }
+    .iter()
+    .map(|daemon| match with_env {
+        true => DaemonRecord::from(daemon).with_env(daemon),
+        false => DaemonRecord::from(daemon),
+    })
+    .collect()
+}
+
+pub fn list_profiles(config: &Config) {
+    let running = daemons::get_all(config);
+    let pid = |name: &str| running.iter().find(|daemon| daemon.socket_name == name).map(|d| d.pid);
+    let row = |name: &str, status: String, autostart: &str| format!("{name:<14} {status:<22} {autostart}");
+    println!("{}", row("NAME", "STATUS".to_string(), "AUTOSTART"));
+    for (name, profile) in config.profiles() {
+        let status = match pid(name) {
+            Some(pid) => format!("running [Pid: {pid}]"),
+            None => "stopped".to_string(),
+        };
+        println!("{}", row(name, status, if profile.autostart { "yes" } else { "no" }));
+    }
+    // daemons launched without a profile
+    for daemon in running.iter().filter(|daemon| config.profile(&daemon.socket_name).is_none()) {
+        let status = format!("running [Pid: {}]", daemon.pid);
+        println!("{}", row(&daemon.socket_name, status, "(no profile)"));
+    }
+}
+
+pub fn list_daemons_short(all_users: bool, config: &Config) {
+    match all_users {
+        true => daemons::get_all_users(config)
+            .iter()
+            .map(|daemon| daemon.socket_name.clone())
+            .collect(),
+        false => daemons::active_daemons_names(config),
+    }
+    .iter()
+    .for_each(|name| println!("{name}"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, ScratchDir};
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn start_on_connect_launches_missing_daemon() {
+        let dir = ScratchDir::new("cli-start");
+        // an Emacs which records its arguments, then fails to launch
+        let emacs = dir.join("emacs");
+        let launched = dir.join("launched");
+        std::fs::write(&emacs, format!("#!/bin/sh\necho \"$@\" > {}\nexit 1\n", launched.display())).unwrap();
+        std::fs::set_permissions(&emacs, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let config = testing::config(&dir, &format!(
+            "emacs_exec = \"{}\"\nstart_on_connect = true\n[daemons.eud-test-start]\nno_init = true\n",
+            emacs.display()
+        ));
+
+        assert!(starts_daemon(false, false, &config));
+        assert!(!starts_daemon(false, true, &config));
+        let timeout = std::time::Duration::from_secs(5);
+        let result = start_if_missing("eud-test-start", LaunchOptions::default(), timeout, &config);
+
+        assert!(matches!(result, Err(Error::Launch { status: Some(_), .. })));
+        assert_eq!(
+            std::fs::read_to_string(&launched).unwrap().trim_end(),
+            format!("--daemon={} -Q", config.server_socket_dir().join("eud-test-start").display()),
+        );
+    }
 }
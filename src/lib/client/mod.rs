@@ -0,0 +1,684 @@
+pub mod protocol;
+
+use crate::config::Config;
+use crate::daemons;
+use crate::error::{Error, Result};
+use clap::ValueEnum;
+use protocol::{Command as ServerCommand, Server};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+
+/// Where the client displays the file it visits.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Frame {
+    /// a new graphical frame
+    #[default]
+    Create,
+    /// the daemon's current frame (or a new one, if there is none)
+    Reuse,
+    /// the terminal `eud` is running in
+    Tty,
+}
+
+/// What to do with a file to visit which does not exist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingFiles {
+    /// visit it as a new file, which Emacs creates when it is saved
+    /// (creating any missing parent directories now)
+    Create,
+    /// exit with an error, visiting nothing
+    #[default]
+    Error,
+    /// ask whether to create it, skipping it if not (or an error, if
+    /// `eud` is not run in a terminal)
+    Ask,
+}
+
+impl MissingFiles {
+    /// The files of `files` to visit under this policy.
+    pub fn apply(self, files: Vec<Location>) -> Result<Vec<Location>> {
+        let interactive = std::io::stdin().is_terminal();
+        let mut visit = Vec::new();
+        let mut declined = None;
+        for location in files {
+            if location.path.exists() {
+                visit.push(location);
+                continue;
+            }
+            let create = match self {
+                MissingFiles::Create => true,
+                MissingFiles::Ask if interactive => confirm(&format!(
+                    "{} does not exist. Create it?", location.path.display()
+                ))?,
+                MissingFiles::Error | MissingFiles::Ask => {
+                    return Err(Error::FileNotFound { path: location.path });
+                }
+            };
+            match create {
+                true => {
+                    if let Some(parent) = location.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                        fs::create_dir_all(parent)?;
+                    }
+                    visit.push(location);
+                }
+                false => declined = declined.or(Some(location.path)),
+            }
+        }
+        match (visit.is_empty(), declined) {
+            (true, Some(path)) => Err(Error::FileNotFound { path }),
+            _ => Ok(visit),
+        }
+    }
+}
+
+/// Ask `question` on the terminal, answered yes or no (the default).
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{question} [y/N] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// A file to visit, and optionally the position within it at which to
+/// place point.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+impl From<PathBuf> for Location {
+    fn from(path: PathBuf) -> Self {
+        Self { path, line: None, column: None }
+    }
+}
+
+impl Location {
+    /// Parse files to visit, each given as `PATH`, `PATH:LINE` or
+    /// `PATH:LINE:COLUMN` (as in compiler and `grep -n` output), or
+    /// preceded by `+LINE` or `+LINE:COLUMN` (as for `emacsclient`). A path
+    /// which exists as given is never split at a colon.
+    pub fn parse_args(args: &[String]) -> Result<Vec<Location>> {
+        let mut locations = Vec::new();
+        let mut position = None;
+        for arg in args {
+            if let Some(spec) = arg.strip_prefix('+') {
+                position = Some(parse_position(spec).ok_or_else(|| {
+                    Error::InvalidArgument(format!("Invalid position `{arg}`; expected +LINE or +LINE:COLUMN"))
+                })?);
+                continue;
+            }
+            let location = match position.take() {
+                Some((line, column)) => Location { path: arg.into(), line: Some(line), column },
+                None => Location::split(arg),
+            };
+            locations.push(location);
+        }
+        match position {
+            Some(_) => Err(Error::InvalidArgument("A position (+LINE:COLUMN) must precede a file".into())),
+            None => Ok(locations),
+        }
+    }
+
+    /// `PATH[:LINE[:COLUMN]]`, unless `arg` exists as a path itself.
+    fn split(arg: &str) -> Location {
+        let number = |n: &str| n.parse::<u32>().ok();
+        // a trailing colon, as in `grep` output, is ignored
+        let trimmed = arg.strip_suffix(':').unwrap_or(arg);
+        let split = trimmed
+            .rsplit_once(':')
+            .filter(|(_, last)| number(last).is_some() && !Path::new(arg).exists());
+        let location = match split {
+            Some((rest, last)) => match rest.rsplit_once(':').filter(|(_, n)| number(n).is_some()) {
+                Some((path, line)) => Location { path: path.into(), line: number(line), column: number(last) },
+                None => Location { path: rest.into(), line: number(last), column: None },
+            },
+            None => Location::from(PathBuf::from(arg)),
+        };
+        match location.path.as_os_str().is_empty() {
+            true => Location::from(PathBuf::from(arg)),
+            false => location,
+        }
+    }
+
+    /// The absolute path to visit, resolving symbolic links if it exists.
+    fn absolute_path(&self) -> Result<PathBuf> {
+        Ok(match self.path.exists() {
+            true => fs::canonicalize(&self.path)?,
+            // a new file, which Emacs creates when it is saved
+            false => std::path::absolute(&self.path)?,
+        })
+    }
+
+    /// The arguments to `emacsclient` which visit this location.
+    fn client_args(&self) -> Result<Vec<String>> {
+        let path = self.absolute_path()?.display().to_string();
+        Ok(match (self.line, self.column) {
+            (Some(line), Some(column)) => vec![format!("+{line}:{column}"), path],
+            (Some(line), None) => vec![format!("+{line}"), path],
+            (None, _) => vec![path],
+        })
+    }
+
+    /// The commands to the server which visit this location.
+    fn server_commands(&self) -> Result<Vec<ServerCommand>> {
+        let position = self.line.map(|line| ServerCommand::Position {
+            line: line as usize,
+            column: self.column.map(|column| column as usize),
+        });
+        Ok(position.into_iter().chain([ServerCommand::File(self.absolute_path()?)]).collect())
+    }
+}
+
+/// `LINE` or `LINE:COLUMN`.
+fn parse_position(spec: &str) -> Option<(u32, Option<u32>)> {
+    match spec.split_once(':') {
+        Some((line, column)) => Some((line.parse().ok()?, Some(column.parse().ok()?))),
+        None => Some((spec.parse().ok()?, None)),
+    }
+}
+
+/// `emacsclient`, attached to the terminal, which opens a frame there.
+#[derive(Clone, Debug)]
+pub struct ClientProcess {
+    server: Server,
+    visit_files: Vec<Location>,
+    alternate_editor: Option<String>,
+}
+
+impl ClientProcess {
+    fn with_daemon(server: Server, visit_files: Vec<Location>) -> Self {
+        Self {
+            server,
+            visit_files,
+            alternate_editor: None,
+        }
+    }
+
+    fn spawn(&self, config: &Config) -> Result<Child> {
+        let mut args = Vec::new();
+        for location in &self.visit_files {
+            args.extend(location.client_args()?);
+        }
+        Command::new(config.emacs_client_exec())
+            .arg("--tty")
+            .arg(match &self.server {
+                Server::Local(socket) => format!("--socket-name={}", socket.display()),
+                Server::Tcp(file) => format!("--server-file={}", file.path.display()),
+            })
+            .arg(format!(
+                "--alternate-editor={}",
+                self.alternate_editor.as_ref().unwrap_or(config.alternative_editor())
+            ))
+            .args(args)
+            .spawn()
+            .map_err(Error::ClientSpawn)
+    }
+}
+
+/// What a client tells the server about itself, as `emacsclient` does:
+/// its working directory and, for a new frame, its environment and display.
+#[derive(Clone, Debug, Default)]
+struct ClientEnv {
+    dir: PathBuf,
+    /// each environment variable, as `VAR=VALUE`
+    vars: Vec<String>,
+    display: Option<String>,
+}
+
+impl ClientEnv {
+    fn current() -> Result<Self> {
+        Ok(Self {
+            dir: std::env::current_dir()?,
+            vars: std::env::vars_os()
+                .map(|(var, value)| format!("{}={}", var.to_string_lossy(), value.to_string_lossy()))
+                .collect(),
+            display: ["DISPLAY", "WAYLAND_DISPLAY"]
+                .iter()
+                .find_map(|var| std::env::var(var).ok().filter(|display| !display.is_empty())),
+        })
+    }
+
+    /// The commands which open a graphical `frame` (or select the current
+    /// frame), in the order `emacsclient` sends them; the server replies
+    /// as soon as the request is carried out, unless it is to `wait`.
+    fn frame_commands(&self, frame: Frame, wait: bool) -> Vec<ServerCommand> {
+        let mut commands: Vec<ServerCommand> = self.vars.iter().cloned().map(ServerCommand::Env).collect();
+        commands.push(ServerCommand::Dir(self.dir.clone()));
+        if !wait {
+            commands.push(ServerCommand::NoWait);
+        }
+        if frame == Frame::Reuse {
+            commands.push(ServerCommand::CurrentFrame);
+        }
+        if let Some(display) = &self.display {
+            commands.push(ServerCommand::Display(display.clone()));
+        }
+        commands.push(ServerCommand::WindowSystem);
+        commands
+    }
+}
+
+/// A client connected to a daemon, by `connect`.
+pub enum Client {
+    /// `emacsclient`, in the terminal (i.e. with `Frame::Tty`)
+    Terminal(Child),
+    /// a request sent to the daemon's server, which replies once it is done
+    Server { daemon: String, server: Server, connection: protocol::Connection },
+}
+
+impl Client {
+    /// Wait until the daemon has visited the files (or, if the client
+    /// waits, until they are finished with), returning anything it printed.
+    pub fn finish(self) -> Result<protocol::Response> {
+        match self {
+            Client::Terminal(mut child) => {
+                let status = wait_attached(&mut child)?;
+                match status.success() {
+                    true => Ok(protocol::Response::default()),
+                    false => Err(Error::ClientFailed { status }),
+                }
+            }
+            Client::Server { daemon, server, connection } => {
+                let response = connection.response()
+                    .map_err(|source| Error::Connection { socket: server.path().to_path_buf(), source })?;
+                check_response(&daemon, response)
+            }
+        }
+    }
+}
+
+/// Fail if the server could not carry out a client's request.
+fn check_response(daemon: &str, response: protocol::Response) -> Result<protocol::Response> {
+    if response.window_system_unsupported {
+        return Err(Error::WindowSystemUnsupported { daemon: daemon.into() });
+    }
+    match response.is_error() {
+        true => Err(Error::ClientRequest { daemon: daemon.into(), message: response.errors.join("\n") }),
+        false => Ok(response),
+    }
+}
+
+/// Open a connection to `server`, and send it `commands`.
+fn send(server: &Server, commands: &[ServerCommand]) -> Result<protocol::Connection> {
+    let connection_error = |source| Error::Connection { socket: server.path().to_path_buf(), source };
+    let mut connection = protocol::Connection::open(server).map_err(connection_error)?;
+    connection.send(commands).map_err(connection_error)?;
+    Ok(connection)
+}
+
+/// The outcome of evaluating an expression in a daemon.
+#[derive(Clone, Debug, Serialize)]
+pub struct Evaluation {
+    pub daemon: String,
+    pub pid: Option<u32>,
+    pub result: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Connect a client to the daemon `daemon_name`, visiting each of
+/// `files` (a directory in `dired`), those which do not exist as
+/// `missing` determines. A graphical frame is opened by speaking to the
+/// daemon's server directly, and a frame in the terminal with
+/// `emacsclient`; the client waits for the files to be finished with if
+/// it is to `wait` (as a client in the terminal always does).
+pub fn connect(
+    daemon_name: &str,
+    files: Vec<Location>,
+    missing: MissingFiles,
+    frame: Frame,
+    wait: bool,
+    config: &Config,
+) -> Result<Client> {
+    let daemon = daemons::find_by_name(daemon_name, config)?;
+    let server = daemon.server()?;
+    let files = missing.apply(files)?;
+    match frame {
+        Frame::Tty => ClientProcess::with_daemon(server, files).spawn(config).map(Client::Terminal),
+        Frame::Create | Frame::Reuse => {
+            visit(daemon.socket_name, server, &files, frame, wait, &ClientEnv::current()?)
+        }
+    }
+}
+
+/// Ask `server` to visit `files` in a graphical `frame`.
+fn visit(
+    daemon: String,
+    server: Server,
+    files: &[Location],
+    frame: Frame,
+    wait: bool,
+    env: &ClientEnv,
+) -> Result<Client> {
+    let mut commands = env.frame_commands(frame, wait);
+    for location in files {
+        commands.extend(location.server_commands()?);
+    }
+    let connection = send(&server, &commands)?;
+    Ok(Client::Server { daemon, server, connection })
+}
+
+/// Wait for a client attached to the terminal (i.e. with `Frame::Tty`)
+/// to exit. The client is in the terminal's foreground process group, so
+/// receives SIGWINCH and SIGINT from the terminal itself; meanwhile `eud`
+/// ignores SIGINT (SIGWINCH is ignored by default), so that it is not
+/// interrupted while the client is still running.
+pub fn wait_attached(client: &mut Child) -> Result<ExitStatus> {
+    // SAFETY: replacing the disposition of SIGINT with `SIG_IGN`, and
+    // restoring the previous disposition, touches no other state
+    let previous = unsafe { libc::signal(libc::SIGINT, libc::SIG_IGN) };
+    let status = client.wait();
+    unsafe { libc::signal(libc::SIGINT, previous) };
+    Ok(status?)
+}
+
+/// The buffer into which `pipe` inserts its input.
+#[derive(Clone, Debug)]
+pub struct PipeTarget {
+    /// the buffer's name; it is created if it does not exist
+    pub buffer: String,
+    /// a major mode to enable in the buffer, e.g. `compilation-mode`
+    pub mode: Option<String>,
+    /// keep the end of the buffer in view in each window showing it, as
+    /// `tail -f` does
+    pub follow: bool,
+    /// the frame in which to show the buffer, if any
+    pub show: Option<Frame>,
+}
+
+/// Insert everything read from `input` at the end of `target`'s buffer in
+/// the daemon `daemon_name`, as it is read (i.e. streaming it), until the
+/// end of `input`. The buffer is set up, and shown, before any input is
+/// read.
+pub fn pipe(
+    daemon_name: &str,
+    target: &PipeTarget,
+    mut input: impl std::io::Read,
+    config: &Config,
+) -> Result<()> {
+    let daemon = daemons::find_by_name(daemon_name, config)?;
+    let server = daemon.server()?;
+    let buffer = lisp_string(&target.buffer);
+    let mode = match &target.mode {
+        Some(mode) => format!(
+            "(let ((mode (intern {}))) (unless (eq major-mode mode) (funcall mode)))",
+            lisp_string(mode)
+        ),
+        None => "nil".into(),
+    };
+    eval_quietly(&server, &daemon.socket_name, &format!(
+        "(with-current-buffer (get-buffer-create {buffer}) {mode} nil)"
+    ))?;
+    if let Some(frame) = target.show {
+        let expr = format!("(switch-to-buffer (get-buffer-create {buffer}))");
+        let mut commands = ClientEnv::current()?.frame_commands(frame, false);
+        commands.push(ServerCommand::Eval(expr));
+        let connection = send(&server, &commands)?;
+        Client::Server { daemon: daemon.socket_name.clone(), server: server.clone(), connection }.finish()?;
+    }
+
+    let mut pending = Vec::new();
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        let read = match input.read(&mut chunk) {
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        pending.extend_from_slice(&chunk[..read]);
+        let text = take_utf8(&mut pending, read == 0);
+        if !text.is_empty() {
+            eval_quietly(&server, &daemon.socket_name, &insert_expr(&buffer, &text, target.follow))?;
+        }
+        if read == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// An expression inserting `text` at the end of `buffer` (a Lisp string),
+/// moving point with it wherever point was at the end, and in every
+/// window showing the buffer if `follow`ing.
+fn insert_expr(buffer: &str, text: &str, follow: bool) -> String {
+    format!(
+        concat!(
+            "(with-current-buffer (get-buffer-create {buffer}) ",
+            "(let ((inhibit-read-only t) (at-end (eobp)) ",
+            "(windows (and {follow} (get-buffer-window-list nil nil t)))) ",
+            "(save-excursion (goto-char (point-max)) (insert {text})) ",
+            "(when at-end (goto-char (point-max))) ",
+            "(dolist (window windows) (set-window-point window (point-max))) nil))",
+        ),
+        buffer = buffer,
+        follow = match follow { true => "t", false => "nil" },
+        text = lisp_string(text),
+    )
+}
+
+/// Take the text of `bytes`, with each invalid sequence replaced, but
+/// leaving behind a character which is incomplete (unless at the `end`
+/// of the input), as it may be completed by the next read.
+fn take_utf8(bytes: &mut Vec<u8>, end: bool) -> String {
+    let mut text = String::new();
+    let mut consumed = 0;
+    loop {
+        let rest = &bytes[consumed..];
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                consumed = bytes.len();
+                break;
+            }
+            Err(e) => {
+                let valid = e.valid_up_to();
+                text.push_str(&String::from_utf8_lossy(&rest[..valid]));
+                consumed += valid;
+                match e.error_len() {
+                    Some(invalid) => consumed += invalid,
+                    None if end => consumed = bytes.len(),
+                    None => break,
+                }
+                text.push(char::REPLACEMENT_CHARACTER);
+            }
+        }
+    }
+    bytes.drain(..consumed);
+    text
+}
+
+/// `text` as a Lisp string literal.
+pub(crate) fn lisp_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Evaluate `expr` in `server`, failing if the daemon signals an error.
+fn eval_quietly(server: &Server, daemon: &str, expr: &str) -> Result<()> {
+    let response = protocol::request(server, &[ServerCommand::Eval(expr.into())], None)
+        .map_err(|source| Error::Connection { socket: server.path().to_path_buf(), source })?;
+    match response.is_error() {
+        true => Err(Error::Eval { daemon: daemon.into(), message: response.errors.join("\n") }),
+        false => Ok(()),
+    }
+}
+
+/// Evaluate `expr` in the daemon `daemon_name`, speaking to its server
+/// directly (i.e. without `emacsclient`).
+pub fn eval(
+    daemon_name: &str,
+    expr: &str,
+    config: &Config,
+) -> Result<Evaluation> {
+    let daemon = daemons::find_by_name(daemon_name, config)?;
+    let server = daemon.server()?;
+    let response = protocol::request(
+        &server,
+        &[
+            ServerCommand::Dir(std::env::current_dir()?),
+            ServerCommand::CurrentFrame,
+            ServerCommand::Eval(expr.into()),
+        ],
+        None,
+    ).map_err(|source| Error::Connection { socket: server.path().to_path_buf(), source })?;
+    Ok(Evaluation {
+        daemon: daemon.socket_name,
+        pid: response.emacs_pid,
+        result: (!response.values.is_empty()).then(|| response.output()),
+        error: (response.is_error()).then(|| response.errors.join("\n")),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ScratchDir;
+    use protocol::tests::fake_server;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn at(path: &str, line: Option<u32>, column: Option<u32>) -> Location {
+        Location { path: path.into(), line, column }
+    }
+
+    #[test]
+    fn parses_locations() {
+        assert_eq!(
+            Location::parse_args(&args(&["src/main.rs:42:7", "notes.org:3", "+10:2", "ReadMe.md", "plain.txt"])).unwrap(),
+            vec![
+                at("src/main.rs", Some(42), Some(7)),
+                at("notes.org", Some(3), None),
+                at("ReadMe.md", Some(10), Some(2)),
+                at("plain.txt", None, None),
+            ],
+        );
+        assert_eq!(Location::parse_args(&args(&["lib.rs:12:"])).unwrap(), vec![at("lib.rs", Some(12), None)]);
+        assert_eq!(Location::parse_args(&args(&["a:b.txt"])).unwrap(), vec![at("a:b.txt", None, None)]);
+        assert!(Location::parse_args(&args(&["+x", "main.rs"])).is_err());
+        assert!(Location::parse_args(&args(&["main.rs", "+3"])).is_err());
+    }
+
+    #[test]
+    fn existing_paths_are_not_split() {
+        let dir = ScratchDir::new("client-paths");
+        let odd = dir.join("odd:12");
+        std::fs::write(&odd, "").unwrap();
+        let odd = odd.display().to_string();
+
+        assert_eq!(Location::parse_args(std::slice::from_ref(&odd)).unwrap(), vec![at(&odd, None, None)]);
+    }
+
+    #[test]
+    fn utf8_split_across_chunks() {
+        let mut pending = "héllo".as_bytes()[..2].to_vec();
+        assert_eq!(take_utf8(&mut pending, false), "h");
+        pending.extend_from_slice(&"héllo".as_bytes()[2..]);
+        assert_eq!(take_utf8(&mut pending, false), "éllo");
+        assert!(pending.is_empty());
+
+        let mut invalid = vec![b'a', 0xff, b'b', 0xc3];
+        assert_eq!(take_utf8(&mut invalid, true), "a\u{fffd}b\u{fffd}");
+
+        // an invalid byte, then a character split between reads
+        let mut pending = vec![0xff, 0xe2, 0x82];
+        assert_eq!(take_utf8(&mut pending, false), "\u{fffd}");
+        assert_eq!(pending, [0xe2, 0x82]);
+        pending.push(0xac);
+        assert_eq!(take_utf8(&mut pending, false), "€");
+        assert_eq!(lisp_string(r#"say "hi" \ bye"#), r#""say \"hi\" \\ bye""#);
+    }
+
+    fn client_env() -> ClientEnv {
+        ClientEnv {
+            dir: "/home/me".into(),
+            vars: vec!["TERM=xterm-256color".into(), "LANG=en_GB.UTF-8".into()],
+            display: Some(":0".into()),
+        }
+    }
+
+    #[test]
+    fn visits_files_in_new_frame() {
+        let scratch = ScratchDir::new("client-visit");
+        let dir = fs::canonicalize(&scratch).unwrap();
+        let notes = dir.join("my notes.org");
+        std::fs::write(&notes, "").unwrap();
+        let (socket, server) = fake_server("visit", &["-emacs-pid 4321"]);
+
+        let client = visit(
+            "work".into(),
+            Server::Local(socket),
+            &[Location { path: notes.clone(), line: Some(3), column: Some(7) }, Location::from(dir.to_path_buf())],
+            Frame::Create,
+            false,
+            &client_env(),
+        ).unwrap();
+        let response = client.finish().unwrap();
+
+        assert_eq!(server.join().unwrap(), format!(
+            concat!(
+                "-env TERM=xterm-256color -env LANG=en_GB.UTF-8 -dir /home/me/ -nowait ",
+                "-display :0 -window-system -position +3:7 -file {}/my&_notes.org -file {}\n",
+            ),
+            dir.display(),
+            dir.display(),
+        ));
+        assert_eq!(response.emacs_pid, Some(4321));
+    }
+
+    #[test]
+    fn waits_for_files_in_current_frame() {
+        let (socket, server) = fake_server("wait", &["-emacs-pid 4321", "-error Aborted&_by&_the&_user"]);
+
+        let client = visit(
+            "work".into(),
+            Server::Local(socket),
+            &[Location::from(PathBuf::from("/etc/hosts"))],
+            Frame::Reuse,
+            true,
+            &ClientEnv { display: None, ..client_env() },
+        ).unwrap();
+        let result = client.finish();
+
+        assert_eq!(
+            server.join().unwrap(),
+            "-env TERM=xterm-256color -env LANG=en_GB.UTF-8 -dir /home/me/ -current-frame -window-system -file /etc/hosts\n",
+        );
+        assert!(matches!(
+            result,
+            Err(Error::ClientRequest { daemon, message }) if daemon == "work" && message == "Aborted by the user"
+        ));
+    }
+
+    #[test]
+    fn window_system_unsupported() {
+        let (socket, server) = fake_server("unsupported", &["-emacs-pid 4321", "-window-system-unsupported"]);
+
+        let result = visit("work".into(), Server::Local(socket), &[], Frame::Create, false, &client_env())
+            .and_then(Client::finish);
+
+        server.join().unwrap();
+        assert!(matches!(result, Err(Error::WindowSystemUnsupported { daemon }) if daemon == "work"));
+    }
+
+    #[test]
+    fn missing_file_policies() {
+        let dir = ScratchDir::new("client-missing");
+        let existing = Location::from(dir.to_path_buf());
+        let new = Location::from(dir.join("new").join("notes.md"));
+        let files = vec![existing.clone(), new.clone()];
+
+        assert!(matches!(
+            MissingFiles::Error.apply(files.clone()),
+            Err(Error::FileNotFound { path }) if path == new.path
+        ));
+        assert_eq!(MissingFiles::Create.apply(files).unwrap(), vec![existing, new]);
+        assert!(dir.join("new").is_dir());
+    }
+}
@@ -0,0 +1,540 @@
+// The protocol spoken between `emacsclient` and an Emacs server over its
+// socket, c.f. `server.el` and `lib-src/emacsclient.c` in Emacs' sources.
+//
+// A request is a single line of space-separated commands (each argument
+// quoted with `quote`), terminated by a newline. The server replies with
+// one line per message, then closes the connection once the request is
+// complete (or, when visiting files without `-nowait`, once the user is
+// done editing them).
+//
+// A server listens on a local socket or, with `server-use-tcp', on a TCP
+// port, which it writes to its "server file" in `server-auth-dir' along
+// with a key; a client must send `-auth KEY` before any other command.
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+
+/// Where an Emacs server listens for clients.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Server {
+    /// a local (Unix domain) socket, at this path
+    Local(PathBuf),
+    /// a TCP port, as found in the server's file
+    Tcp(ServerFile),
+}
+
+/// The file in which a TCP server records where it listens, which reads
+///   HOST:PORT PID
+///   KEY
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerFile {
+    pub path: PathBuf,
+    /// `HOST:PORT`
+    pub address: String,
+    /// the process id of the server, within its own PID namespace
+    pub pid: Option<u32>,
+    /// the key with which clients authenticate
+    pub key: String,
+}
+
+impl ServerFile {
+    pub fn read(path: &Path) -> std::io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::parse(path, &contents).ok_or_else(|| std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} is not an Emacs server file", path.display()),
+        ))
+    }
+
+    fn parse(path: &Path, contents: &str) -> Option<Self> {
+        let mut lines = contents.lines();
+        let first = lines.next()?;
+        let (address, pid) = first.split_once(' ').unwrap_or((first, ""));
+        let key = lines.next()?.trim();
+        (!address.is_empty() && !key.is_empty()).then(|| Self {
+            path: path.to_path_buf(),
+            address: address.to_string(),
+            pid: pid.trim().parse().ok(),
+            key: key.to_string(),
+        })
+    }
+}
+
+impl Server {
+    /// The path to the server's socket, or to its server file.
+    pub fn path(&self) -> &Path {
+        match self {
+            Server::Local(socket) => socket,
+            Server::Tcp(file) => &file.path,
+        }
+    }
+}
+
+impl fmt::Display for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Server::Local(socket) => write!(f, "{}", socket.display()),
+            Server::Tcp(file) => write!(f, "tcp://{}", file.address),
+        }
+    }
+}
+
+
+/// A command sent by the client, as part of a request to the server.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// `-auth KEY`: authenticate with a TCP server (which must come first);
+    /// the key is sent as it is, since the server matches it unquoted
+    Auth(String),
+    /// `-env VAR=VALUE`: set an environment variable for new frames
+    Env(String),
+    /// `-dir DIR`: the client's working directory
+    Dir(PathBuf),
+    /// `-nowait`: do not wait for visited files to be finished with
+    NoWait,
+    /// `-current-frame`: use the selected frame, rather than creating one
+    CurrentFrame,
+    /// `-display DISPLAY`: the X display on which to create a frame
+    Display(String),
+    /// `-window-system`: create a graphical frame
+    WindowSystem,
+    /// `-tty NAME TYPE`: create a frame on the terminal device NAME
+    Tty { name: String, term: String },
+    /// `-position +LINE[:COLUMN]`: position at which to visit the next `File`
+    Position { line: usize, column: Option<usize> },
+    /// `-file PATH`: visit the (absolute) PATH
+    File(PathBuf),
+    /// `-eval EXPR`: evaluate EXPR, printing the result
+    Eval(String),
+}
+
+impl Command {
+    fn encode(&self) -> String {
+        match self {
+            Command::Auth(key) => format!("-auth {key}"),
+            Command::Env(var) => format!("-env {}", quote(var)),
+            Command::Dir(dir) => {
+                // a trailing slash marks the argument as a directory
+                let mut dir = dir.to_string_lossy().into_owned();
+                if !dir.ends_with('/') {
+                    dir.push('/');
+                }
+                format!("-dir {}", quote(&dir))
+            }
+            Command::NoWait => "-nowait".into(),
+            Command::CurrentFrame => "-current-frame".into(),
+            Command::Display(display) => format!("-display {}", quote(display)),
+            Command::WindowSystem => "-window-system".into(),
+            Command::Tty { name, term } => format!("-tty {} {}", quote(name), quote(term)),
+            Command::Position { line, column: None } => format!("-position +{line}"),
+            Command::Position { line, column: Some(column) } => format!("-position +{line}:{column}"),
+            Command::File(path) => format!("-file {}", quote(&path.to_string_lossy())),
+            Command::Eval(expr) => format!("-eval {}", quote(expr)),
+        }
+    }
+}
+
+
+/// A message sent by the server, in reply to a request.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reply {
+    /// `-emacs-pid PID`: the process id of the server
+    EmacsPid(u32),
+    /// `-print STRING`: the start of a value to be printed, on a new line
+    Print(String),
+    /// `-print-nonl STRING`: the continuation of a value being printed
+    PrintNonl(String),
+    /// `-error DESCRIPTION`: the request failed
+    Error(String),
+    /// `-window-system-unsupported`: no graphical frame can be created
+    WindowSystemUnsupported,
+    /// `-suspend`: the client should suspend itself
+    Suspend,
+    /// any message not (yet) understood
+    Unknown(String),
+}
+
+impl Reply {
+    pub fn parse(line: &str) -> Self {
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "-emacs-pid" => match arg.trim().parse() {
+                Ok(pid) => Reply::EmacsPid(pid),
+                Err(_) => Reply::Unknown(line.into()),
+            },
+            "-print" => Reply::Print(unquote(arg)),
+            "-print-nonl" => Reply::PrintNonl(unquote(arg)),
+            "-error" => Reply::Error(unquote(arg)),
+            "-window-system-unsupported" => Reply::WindowSystemUnsupported,
+            "-suspend" => Reply::Suspend,
+            _ => Reply::Unknown(line.into()),
+        }
+    }
+}
+
+
+/// Everything the server replied with, once a request is complete.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Response {
+    pub emacs_pid: Option<u32>,
+    /// each value printed, e.g. the result of each `Eval`
+    pub values: Vec<String>,
+    pub errors: Vec<String>,
+    pub window_system_unsupported: bool,
+}
+
+impl Response {
+    pub fn is_error(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    /// The printed values, as `emacsclient` would write them to stdout.
+    pub fn output(&self) -> String {
+        self.values.join("\n")
+    }
+
+    fn update(&mut self, reply: Reply) {
+        match reply {
+            Reply::EmacsPid(pid) => self.emacs_pid = Some(pid),
+            Reply::Print(value) => self.values.push(value),
+            Reply::PrintNonl(value) => match self.values.last_mut() {
+                Some(last) => last.push_str(&value),
+                None => self.values.push(value),
+            },
+            Reply::Error(error) => self.errors.push(error),
+            Reply::WindowSystemUnsupported => self.window_system_unsupported = true,
+            Reply::Suspend | Reply::Unknown(_) => (),
+        }
+    }
+}
+
+
+/// An open connection to an Emacs server.
+pub struct Connection {
+    stream: Stream,
+    /// the key with which to authenticate, for a TCP server
+    key: Option<String>,
+}
+
+enum Stream {
+    Local(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Connection {
+    pub fn open(server: &Server) -> std::io::Result<Self> {
+        Self::connect(server, None)
+    }
+
+    /// Open a connection, waiting no longer than `timeout` (if given) to
+    /// connect, and then to send or receive (see `set_timeout`).
+    pub fn connect(server: &Server, timeout: Option<Duration>) -> std::io::Result<Self> {
+        let connection = match server {
+            Server::Local(socket) => Self { stream: Stream::Local(UnixStream::connect(socket)?), key: None },
+            Server::Tcp(file) => Self {
+                stream: Stream::Tcp(connect_tcp(&file.address, timeout)?),
+                key: Some(file.key.clone()),
+            },
+        };
+        connection.set_timeout(timeout)?;
+        Ok(connection)
+    }
+
+    /// Limit how long to wait on the server when sending or receiving;
+    /// `None` waits indefinitely.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match &self.stream {
+            Stream::Local(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)
+            }
+            Stream::Tcp(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)
+            }
+        }
+    }
+
+    pub fn send(&mut self, commands: &[Command]) -> std::io::Result<()> {
+        let auth = self.key.clone().map(Command::Auth);
+        let mut request = auth
+            .iter()
+            .chain(commands)
+            .map(Command::encode)
+            .collect::<Vec<String>>()
+            .join(" ");
+        request.push('\n');
+        self.stream.write_all(request.as_bytes())?;
+        self.stream.flush()
+    }
+
+    /// Iterate over the server's replies, until it closes the connection.
+    pub fn replies(self) -> impl Iterator<Item = std::io::Result<Reply>> {
+        BufReader::new(self.stream)
+            .lines()
+            .map(|line| line.map(|line| Reply::parse(&line)))
+    }
+
+    /// Wait for the server to complete the request, collecting its replies.
+    pub fn response(self) -> std::io::Result<Response> {
+        let mut response = Response::default();
+        for reply in self.replies() {
+            response.update(reply?);
+        }
+        Ok(response)
+    }
+}
+
+
+/// Connect to `address` (`HOST:PORT`), trying each address it resolves
+/// to in turn, each for up to `timeout` (if given).
+fn connect_tcp(address: &str, timeout: Option<Duration>) -> std::io::Result<TcpStream> {
+    let Some(timeout) = timeout else {
+        return TcpStream::connect(address);
+    };
+    let mut last_error = None;
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("{address} does not resolve to any address"),
+    )))
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Local(stream) => stream.read(buf),
+            Stream::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Local(stream) => stream.write(buf),
+            Stream::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Local(stream) => stream.flush(),
+            Stream::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+
+/// Send `commands` to `server`, and wait for its response.
+pub fn request(
+    server: &Server,
+    commands: &[Command],
+    timeout: Option<Duration>,
+) -> std::io::Result<Response> {
+    let mut connection = Connection::connect(server, timeout)?;
+    connection.send(commands)?;
+    connection.response()
+}
+
+
+/// Quote an argument as `emacsclient` does: `&` becomes `&&`, a space
+/// `&_`, a newline `&n`, and a leading `-` becomes `&-` (so it is not
+/// mistaken for a command).
+pub fn quote(arg: &str) -> String {
+    let mut quoted = String::with_capacity(arg.len());
+    for (i, c) in arg.chars().enumerate() {
+        match c {
+            '&' => quoted.push_str("&&"),
+            ' ' => quoted.push_str("&_"),
+            '\n' => quoted.push_str("&n"),
+            '-' if i == 0 => quoted.push_str("&-"),
+            c => quoted.push(c),
+        }
+    }
+    quoted
+}
+
+/// Reverse the quoting of `quote` (the server may also quote a `-` which
+/// is not leading).
+pub fn unquote(arg: &str) -> String {
+    let mut unquoted = String::with_capacity(arg.len());
+    let mut chars = arg.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => match chars.next() {
+                Some('_') => unquoted.push(' '),
+                Some('n') => unquoted.push('\n'),
+                Some(c) => unquoted.push(c),
+                None => unquoted.push('&'),
+            },
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    /// Serve a single request on a fresh socket, replying with `replies`;
+    /// the request received is returned when the thread is joined.
+    pub(crate) fn fake_server(name: &str, replies: &'static [&'static str]) -> (PathBuf, thread::JoinHandle<String>) {
+        let dir = std::env::temp_dir().join(format!("eud-protocol-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("server");
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let mut stream = stream;
+            for reply in replies {
+                stream.write_all(reply.as_bytes()).unwrap();
+                stream.write_all(b"\n").unwrap();
+            }
+            drop(stream);
+            std::fs::remove_dir_all(dir).unwrap();
+            request
+        });
+        (socket, server)
+    }
+
+    #[test]
+    fn quoting_round_trips() {
+        let arg = "-a & b\n(message \"-x\")";
+        assert_eq!(quote(arg), "&-a&_&&&_b&n(message&_\"-x\")");
+        assert_eq!(unquote(&quote(arg)), arg);
+        assert_eq!(unquote("&-&-foo&-bar"), "--foo-bar");
+    }
+
+    #[test]
+    fn commands_are_encoded() {
+        assert_eq!(Command::Dir("/home/me".into()).encode(), "-dir /home/me/");
+        assert_eq!(
+            Command::Tty { name: "/dev/pts/1".into(), term: "xterm-256color".into() }.encode(),
+            "-tty /dev/pts/1 xterm-256color"
+        );
+        assert_eq!(Command::Position { line: 42, column: Some(7) }.encode(), "-position +42:7");
+        assert_eq!(Command::Position { line: 3, column: None }.encode(), "-position +3");
+        assert_eq!(Command::File("/tmp/a file.txt".into()).encode(), "-file /tmp/a&_file.txt");
+    }
+
+    #[test]
+    fn replies_are_parsed() {
+        assert_eq!(Reply::parse("-emacs-pid 1234"), Reply::EmacsPid(1234));
+        assert_eq!(Reply::parse("-print (1&_2)"), Reply::Print("(1 2)".into()));
+        assert_eq!(Reply::parse("-print-nonl &n3)"), Reply::PrintNonl("\n3)".into()));
+        assert_eq!(Reply::parse("-error Aborted&_by&_the&_user"), Reply::Error("Aborted by the user".into()));
+        assert_eq!(Reply::parse("-window-system-unsupported"), Reply::WindowSystemUnsupported);
+        assert_eq!(Reply::parse("-what"), Reply::Unknown("-what".into()));
+    }
+
+    #[test]
+    fn eval_against_fake_server() {
+        let (socket, server) = fake_server("eval", &[
+            "-emacs-pid 4321",
+            "-print \"hello&_",
+            "-print-nonl world\"",
+        ]);
+
+        let response = request(
+            &Server::Local(socket),
+            &[Command::Dir("/tmp".into()), Command::CurrentFrame, Command::Eval("(concat \"hello \" \"world\")".into())],
+            Some(Duration::from_secs(5)),
+        ).unwrap();
+
+        assert_eq!(server.join().unwrap(), "-dir /tmp/ -current-frame -eval (concat&_\"hello&_\"&_\"world\")\n");
+        assert_eq!(response.emacs_pid, Some(4321));
+        assert_eq!(response.output(), "\"hello world\"");
+        assert!(!response.is_error());
+    }
+
+    #[test]
+    fn error_from_fake_server() {
+        let (socket, server) = fake_server("error", &[
+            "-emacs-pid 4321",
+            "-error Symbol’s&_value&_as&_variable&_is&_void:&_foo",
+        ]);
+
+        let mut connection = Connection::open(&Server::Local(socket)).unwrap();
+        connection.send(&[Command::Eval("foo".into())]).unwrap();
+        let replies = connection.replies().collect::<std::io::Result<Vec<Reply>>>().unwrap();
+        server.join().unwrap();
+
+        assert_eq!(replies, vec![
+            Reply::EmacsPid(4321),
+            Reply::Error("Symbol’s value as variable is void: foo".into()),
+        ]);
+    }
+
+    #[test]
+    fn authenticates_with_tcp_server() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let file = ServerFile::parse(
+            Path::new("/tmp/emacs-server/work"),
+            &format!("{} 4321\n-a&b-c", listener.local_addr().unwrap()),
+        ).unwrap();
+        assert_eq!(file.pid, Some(4321));
+        assert!(ServerFile::parse(Path::new("work"), "127.0.0.1:1234 4321\n").is_none());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            BufReader::new(stream.try_clone().unwrap()).read_line(&mut request).unwrap();
+            let mut stream = stream;
+            stream.write_all(b"-emacs-pid 4321\n-print t\n").unwrap();
+            request
+        });
+
+        let response = request(&Server::Tcp(file), &[Command::Eval("t".into())], Some(Duration::from_secs(5))).unwrap();
+
+        assert_eq!(server.join().unwrap(), "-auth -a&b-c -eval t\n");
+        assert_eq!(response.output(), "t");
+    }
+
+    #[test]
+    fn tcp_connect_times_out() {
+        // a non-routable address, at which a connection neither succeeds
+        // nor is refused
+        let file = ServerFile::parse(Path::new("box"), "10.255.255.1:4242 1\nkey").unwrap();
+        let start = std::time::Instant::now();
+
+        let result = request(&Server::Tcp(file), &[Command::Eval("t".into())], Some(Duration::from_millis(200)));
+
+        assert!(result.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn request_times_out() {
+        let dir = std::env::temp_dir().join(format!("eud-protocol-{}-timeout", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("server");
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        let result = request(&Server::Local(socket.clone()), &[Command::Eval("t".into())], Some(Duration::from_millis(50)));
+
+        assert!(result.is_err());
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
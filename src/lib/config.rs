@@ -1,9 +1,16 @@
 use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
+use std::ffi::OsString;
+use std::fmt;
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
+use ::config::{Environment, File};
+use serde::Deserialize;
 use standard_styled::{Colorize, Style};
+use crate::client::MissingFiles;
+use crate::project::ProjectEnv;
 
 
 pub struct Config {
@@ -11,47 +18,180 @@ pub struct Config {
     emacs_client_exec: String,
     default_socket: String,
     server_socket_dir: PathBuf,   // c.f. `server-socket-dir' in emacs
+    server_auth_dir: PathBuf,     // c.f. `server-auth-dir' in emacs
     editor: String,
     style: Style,
+    profiles: BTreeMap<String, Profile>,
+    project_env: ProjectEnv,
+    start_on_connect: bool,
+    missing_files: MissingFiles,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        let emacs_exec = env::var("EMACS_EXEC")
-            .unwrap_or("emacs".into());
-        let emacs_client_exec = env::var("EMACS_CLIENT_EXEC")
-            .unwrap_or("emacsclient".into());
-        let server_socket_dir = create_server_socket_dir(
-            "~/.emacs.d/sockets/"  // CAUTION: hardcoded (but intentionally)
-        ).expect("Could not create socket directory at `~/.emacs.d/sockets/` .");
-
-        let default_style = Style {
-            spinner: vec![
-                "(●     )",
-                "( ●    )",
-                "(  ●   )",
-                "(   ●  )",
-                "(    ● )",
-                "(     ●)",
-            ],
-            stdout_style: Box::new(|s: &str| s.blue() ),
-            stderr_style: Box::new(|s: &str| s.yellow() ),
-            message_style: Box::new(|s: &str| s.bold().truecolor(127, 90, 182) ),
-            end_message: Some(" Launched Emacs daemon  🚀 ".to_string()),
-        };
+/// How to launch a daemon of a given name, as set in a `[daemons.<name>]`
+/// table of the configuration file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// Emacs executable, in place of `emacs_exec`
+    pub emacs: Option<String>,
+    pub init_directory: Option<PathBuf>,
+    /// load no init files (Emacs' `-Q`)
+    pub no_init: bool,
+    pub load: Vec<PathBuf>,
+    pub eval: Vec<String>,
+    /// further arguments passed to Emacs
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    pub cwd: Option<PathBuf>,
+    /// launch this daemon with `eud autostart`
+    pub autostart: bool,
+    /// listen on TCP, rather than a local socket (implied by `host` or
+    /// `port`)
+    pub tcp: bool,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+}
+
+/// Values which may be set in the configuration file, or as environment
+/// variables prefixed with `EUD_` (e.g. `EUD_DEFAULT_SOCKET=work`).
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    emacs_exec: String,
+    emacs_client_exec: String,
+    default_socket: String,
+    server_socket_dir: Option<PathBuf>,
+    /// where TCP servers write their server files (by default, where
+    /// Emacs does)
+    server_auth_dir: Option<PathBuf>,
+    alternate_editor: String,
+    /// resolve `server_socket_dir` the way Emacs resolves `server-socket-dir'
+    /// (when it is not set explicitly)
+    emacs_compatible: bool,
+    #[serde(default)]
+    daemons: BTreeMap<String, Profile>,
+    /// the environment in which `eud project` launches a project's daemon
+    #[serde(default)]
+    project_env: ProjectEnv,
+    /// launch a daemon which is not running on `eud connect`
+    start_on_connect: bool,
+    /// what `eud connect` does with files which do not exist
+    #[serde(default)]
+    missing_files: MissingFiles,
+}
+
+/// Values passed on the command line, which take precedence over the
+/// configuration file and the environment.
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    pub emacs_exec: Option<String>,
+    pub emacs_client_exec: Option<String>,
+    pub default_socket: Option<String>,
+    pub server_socket_dir: Option<PathBuf>,
+    pub emacs_compatible: Option<bool>,
+}
+
+/// An error encountered while loading the configuration, with the file
+/// (and line, where it can be found) that the offending value came from.
+#[derive(Debug)]
+pub struct ConfigError {
+    file: Option<PathBuf>,
+    line: Option<usize>,
+    source: Box<::config::ConfigError>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(
+                f, "Invalid configuration at {}:{}: {}", file.display(), line, self.source
+            ),
+            (Some(file), None) => write!(
+                f, "Invalid configuration in {}: {}", file.display(), self.source
+            ),
+            (None, _) => write!(f, "Invalid configuration: {}", self.source),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        // the message already includes that of `self.source`
+        self.source.source()
+    }
+}
 
+impl Default for Settings {
+    fn default() -> Self {
         Self {
-            emacs_exec,
-            emacs_client_exec,
+            emacs_exec: env::var("EMACS_EXEC")
+                .unwrap_or("emacs".into()),
+            emacs_client_exec: env::var("EMACS_CLIENT_EXEC")
+                .unwrap_or("emacsclient".into()),
             default_socket: "server".to_string(),
-            server_socket_dir,
-            editor: "nano".to_string(),
-            style: default_style,
+            server_socket_dir: None,
+            server_auth_dir: None,
+            alternate_editor: "nano".to_string(),
+            emacs_compatible: false,
+            daemons: BTreeMap::new(),
+            project_env: ProjectEnv::None,
+            start_on_connect: false,
+            missing_files: MissingFiles::Error,
         }
     }
 }
 
 impl Config {
+    /// Load the configuration in layers: built-in defaults, then the
+    /// configuration `file` (or `$XDG_CONFIG_HOME/eud/config.toml` if
+    /// none is given), then `EUD_*` environment variables, then
+    /// `overrides` from the command line.
+    pub fn load(
+        file: Option<&Path>,
+        overrides: &Overrides,
+    ) -> Result<Self, ConfigError> {
+        Self::load_with_env(file, overrides, None)
+    }
+
+    pub(crate) fn load_with_env(
+        file: Option<&Path>,
+        overrides: &Overrides,
+        env: Option<HashMap<String, String>>,
+    ) -> Result<Self, ConfigError> {
+        // only a file given explicitly (i.e. with `--config`) is required to exist
+        let (file, required) = match file {
+            Some(path) => (Some(path.to_path_buf()), true),
+            None => (default_config_file(), false),
+        };
+        let settings = Settings::load(file.as_deref(), required, overrides, env)
+            .map_err(|source| ConfigError::locate(source, file.as_deref()))?;
+        let socket_dir = settings.resolve_server_socket_dir();
+        let server_auth_dir = settings.resolve_server_auth_dir();
+        let server_socket_dir = create_server_socket_dir(&socket_dir)
+            .map_err(|e| ConfigError {
+                file: None,
+                line: None,
+                source: Box::new(::config::ConfigError::Message(format!(
+                    "could not create socket directory at `{}`: {e}",
+                    socket_dir.display()
+                ))),
+            })?;
+
+        Ok(Self {
+            emacs_exec: settings.emacs_exec,
+            emacs_client_exec: settings.emacs_client_exec,
+            default_socket: settings.default_socket,
+            server_socket_dir,
+            server_auth_dir,
+            editor: settings.alternate_editor,
+            style: default_style(),
+            profiles: settings.daemons,
+            project_env: settings.project_env,
+            start_on_connect: settings.start_on_connect,
+            missing_files: settings.missing_files,
+        })
+    }
+
     pub fn emacs_exec(&self) -> &String {
         &self.emacs_exec
     }
@@ -80,20 +220,281 @@ impl Config {
             emacs_client_exec,
             default_socket,
             server_socket_dir,
+            server_auth_dir: emacs_auth_dir(),
             editor,
             style,
+            profiles: BTreeMap::new(),
+            project_env: ProjectEnv::None,
+            start_on_connect: false,
+            missing_files: MissingFiles::Error,
         }
     }
     pub fn server_socket_dir(&self) -> &PathBuf {
         &self.server_socket_dir
     }
+    /// The directory in which a daemon listening on TCP writes its server
+    /// file (see `eud new --tcp`).
+    pub fn server_auth_dir(&self) -> &PathBuf {
+        &self.server_auth_dir
+    }
+    /// Every directory in which a daemon's socket may be found: `eud`'s
+    /// own socket directory, followed by those Emacs uses by default.
+    pub fn socket_search_dirs(&self) -> Vec<PathBuf> {
+        let uid = current_uid();
+        let mut dirs = vec![self.server_socket_dir.clone()];
+        let candidates = [
+            env::var_os("XDG_RUNTIME_DIR").map(|dir| emacs_socket_dir(Some(dir), None, uid)),
+            env::var_os("TMPDIR").map(|dir| emacs_socket_dir(None, Some(dir), uid)),
+            Some(emacs_socket_dir(None, None, uid)),
+        ];
+        for dir in candidates.into_iter().flatten() {
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        dirs
+    }
     pub fn style(&self) -> &Style {
         &self.style
     }
+    /// The profile of the daemon named `name`, if one is configured.
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.get(name)
+    }
+    /// Every configured profile, by daemon name.
+    pub fn profiles(&self) -> &BTreeMap<String, Profile> {
+        &self.profiles
+    }
+    pub fn project_env(&self) -> ProjectEnv {
+        self.project_env
+    }
+    /// Whether `eud connect` launches a daemon which is not running.
+    pub fn start_on_connect(&self) -> bool {
+        self.start_on_connect
+    }
+    /// What `eud connect` does with files which do not exist.
+    pub fn missing_files(&self) -> MissingFiles {
+        self.missing_files
+    }
+}
+
+impl Settings {
+    fn load(
+        file: Option<&Path>,
+        required: bool,
+        overrides: &Overrides,
+        env: Option<HashMap<String, String>>,
+    ) -> Result<Self, ::config::ConfigError> {
+        let defaults = Settings::default();
+        let mut builder = ::config::Config::builder()
+            .set_default("emacs_exec", defaults.emacs_exec)?
+            .set_default("emacs_client_exec", defaults.emacs_client_exec)?
+            .set_default("default_socket", defaults.default_socket)?
+            .set_default("alternate_editor", defaults.alternate_editor)?
+            .set_default("emacs_compatible", defaults.emacs_compatible)?
+            .set_default("start_on_connect", defaults.start_on_connect)?;
+        if let Some(path) = file {
+            builder = builder.add_source(File::from(path).required(required));
+        }
+        builder
+            .add_source(Environment::with_prefix("EUD").try_parsing(true).source(Some(known_env(env))))
+            .set_override_option("emacs_exec", overrides.emacs_exec.clone())?
+            .set_override_option("emacs_client_exec", overrides.emacs_client_exec.clone())?
+            .set_override_option("default_socket", overrides.default_socket.clone())?
+            .set_override_option(
+                "server_socket_dir",
+                overrides.server_socket_dir.as_deref().map(path_value),
+            )?
+            .set_override_option("emacs_compatible", overrides.emacs_compatible)?
+            .build()?
+            .try_deserialize()
+    }
+
+    fn resolve_server_auth_dir(&self) -> PathBuf {
+        match &self.server_auth_dir {
+            Some(dir) => expand_tilde_as_home(dir).into_owned(),
+            None => emacs_auth_dir(),
+        }
+    }
+
+    fn resolve_server_socket_dir(&self) -> PathBuf {
+        match (&self.server_socket_dir, self.emacs_compatible) {
+            (Some(dir), _) => dir.clone(),
+            (None, false) => PathBuf::from(EUD_SOCKET_DIR),
+            (None, true) => emacs_socket_dir(
+                env::var_os("XDG_RUNTIME_DIR"),
+                env::var_os("TMPDIR"),
+                current_uid(),
+            ),
+        }
+    }
+}
+
+impl ConfigError {
+    /// Attach the file, and the line within it, that a configuration
+    /// error originated from (if it came from the file at all).
+    fn locate(source: ::config::ConfigError, file: Option<&Path>) -> Self {
+        let key = match &source {
+            ::config::ConfigError::Type { key, .. } => key.clone(),
+            ::config::ConfigError::Message(msg) => msg
+                .strip_prefix("unknown field `")
+                .and_then(|rest| rest.split_once('`'))
+                .map(|(key, _)| key.to_string()),
+            _ => None,
+        };
+        let from_file = match &source {
+            ::config::ConfigError::FileParse { .. } => true,
+            ::config::ConfigError::Type { origin, .. } => origin.is_some()
+                && origin.as_deref() != Some("the environment"),
+            _ => false,
+        };
+        let line = file
+            .zip(key.as_deref())
+            .and_then(|(file, key)| find_key_line(file, key));
+        Self {
+            file: if from_file || line.is_some() { file.map(Path::to_path_buf) } else { None },
+            line,
+            source: Box::new(source),
+        }
+    }
+}
+
+
+fn default_style() -> Style {
+    Style {
+        spinner: vec![
+            "(●     )",
+            "( ●    )",
+            "(  ●   )",
+            "(   ●  )",
+            "(    ● )",
+            "(     ●)",
+        ],
+        stdout_style: Box::new(|s: &str| s.blue() ),
+        stderr_style: Box::new(|s: &str| s.yellow() ),
+        message_style: Box::new(|s: &str| s.bold().truecolor(127, 90, 182) ),
+        end_message: Some(" Launched Emacs daemon  🚀 ".to_string()),
+        failure_message: Some(" Failed to launch Emacs daemon  💥 ".to_string()),
+    }
 }
 
 
-fn expand_tilde_as_home<P: AsRef<Path>>(path: &P) -> Cow<Path> {
+/// The socket directory used by `eud`, unless configured otherwise.
+const EUD_SOCKET_DIR: &str = "~/.emacs.d/sockets/";
+
+
+/// The default value of `server-socket-dir' in Emacs, i.e.
+/// `$XDG_RUNTIME_DIR/emacs`, or else `$TMPDIR/emacs$UID`, or else
+/// `/tmp/emacs$UID`.
+pub(crate) fn emacs_socket_dir(
+    xdg_runtime_dir: Option<OsString>,
+    tmpdir: Option<OsString>,
+    uid: u32,
+) -> PathBuf {
+    let non_empty = |dir: Option<OsString>| dir.filter(|dir| !dir.is_empty());
+    match non_empty(xdg_runtime_dir) {
+        Some(runtime_dir) => PathBuf::from(runtime_dir).join("emacs"),
+        None => non_empty(tmpdir)
+            .map_or_else(|| PathBuf::from("/tmp"), PathBuf::from)
+            .join(format!("emacs{uid}")),
+    }
+}
+
+
+/// The default value of `server-auth-dir' in Emacs, i.e. `server/` in
+/// the user's Emacs directory: `~/.emacs.d`, if it exists, or else
+/// `$XDG_CONFIG_HOME/emacs`, if that exists, or else `~/.emacs.d`.
+fn emacs_auth_dir() -> PathBuf {
+    let home = dirs::home_dir().unwrap_or_default();
+    let xdg = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map_or_else(|| home.join(".config"), PathBuf::from)
+        .join("emacs");
+    let emacs_dir = match (home.join(".emacs.d").exists(), xdg.exists()) {
+        (false, true) => xdg,
+        _ => home.join(".emacs.d"),
+    };
+    emacs_dir.join("server")
+}
+
+
+pub(crate) fn current_uid() -> u32 {
+    // SAFETY: `getuid` is always successful and has no side-effects
+    unsafe { libc::getuid() }
+}
+
+
+/// The keys of `Settings` which may be set as `EUD_*` environment variables.
+const ENV_KEYS: &[&str] = &[
+    "emacs_exec",
+    "emacs_client_exec",
+    "default_socket",
+    "server_socket_dir",
+    "server_auth_dir",
+    "alternate_editor",
+    "emacs_compatible",
+    "project_env",
+    "start_on_connect",
+    "missing_files",
+];
+
+
+/// The `EUD_*` variables of `env` (or of the process' environment) which
+/// set a known key: unknown keys are only rejected in the configuration
+/// file, as any other `EUD_*` variable may be in the environment.
+fn known_env(env: Option<HashMap<String, String>>) -> HashMap<String, String> {
+    env.unwrap_or_else(|| {
+        env::vars_os()
+            .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+            .collect()
+    })
+    .into_iter()
+    .filter(|(key, _)| {
+        key.to_lowercase()
+            .strip_prefix("eud_")
+            .is_some_and(|key| ENV_KEYS.contains(&key))
+    })
+    .collect()
+}
+
+
+fn path_value(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+
+/// `$XDG_CONFIG_HOME/eud/config.{toml,yaml,yml}`, whichever exists first
+/// (defaulting to the `.toml` file, which need not exist).
+fn default_config_file() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))?;
+    let dir = config_home.join("eud");
+    ["config.toml", "config.yaml", "config.yml"]
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.exists())
+        .or(Some(dir.join("config.toml")))
+}
+
+
+/// Find the (1-indexed) line in `file` on which top-level `key` is set.
+fn find_key_line(file: &Path, key: &str) -> Option<usize> {
+    fs::read_to_string(file).ok()?
+        .lines()
+        .position(|line| {
+            line.trim_start()
+                .trim_start_matches(['"', '\''])
+                .strip_prefix(key)
+                .map(|rest| rest.trim_start_matches(['"', '\'']).trim_start())
+                .is_some_and(|rest| rest.starts_with('=') || rest.starts_with(':'))
+        })
+        .map(|index| index + 1)
+}
+
+
+pub(crate) fn expand_tilde_as_home<P: AsRef<Path>>(path: &P) -> Cow<'_, Path> {
     let path = path.as_ref();
 
     match path.starts_with("~") {
@@ -117,13 +518,11 @@ where
     // `(when (executable-find "eud")
     //     (setq server-socket-dir
     //       (shell-command-to-string "eud server-socket-dir-path")))`
-    let server_socket_dir = std::fs::canonicalize(
-        expand_tilde_as_home(
-            &PathBuf::from(&dir_path)
-        )
-    )?;
-    // create `sockets` directory
+    let server_socket_dir = expand_tilde_as_home(&PathBuf::from(&dir_path)).into_owned();
+    // create `sockets` directory (before canonicalising, which requires
+    // that the path exists)
     std::fs::create_dir_all(&server_socket_dir)?;
+    let server_socket_dir = std::fs::canonicalize(server_socket_dir)?;
     // .. and ensure permissions are appropriate (rwx------)
     let mut perms = fs::metadata(&server_socket_dir)?.permissions();
     perms.set_mode(0o700);
@@ -131,3 +530,141 @@ where
 
     Ok(server_socket_dir)
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ScratchDir;
+
+    fn load(file: Option<&Path>, env: &[(&str, &str)], overrides: &Overrides) -> Result<Config, ConfigError> {
+        let env = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Config::load_with_env(file, overrides, Some(env))
+    }
+
+    #[test]
+    fn layers_file_then_env_then_overrides() {
+        let dir = ScratchDir::new("config-layers");
+        let file = dir.join("config.toml");
+        fs::write(&file, format!(
+            "default_socket = \"work\"\nalternate_editor = \"vi\"\nemacs_exec = \"emacs-29\"\nserver_socket_dir = \"{}\"\n",
+            dir.join("sockets").display()
+        )).unwrap();
+
+        let config = load(
+            Some(&file),
+            &[("EUD_ALTERNATE_EDITOR", "ed"), ("EUD_EMACS_EXEC", "emacs-30"), ("EUD_START_ON_CONNECT", "true")],
+            &Overrides { emacs_exec: Some("emacs-31".into()), ..Default::default() },
+        ).unwrap();
+
+        assert_eq!(config.default_socket_name(), "work");
+        assert_eq!(config.alternative_editor(), "ed");
+        assert_eq!(config.emacs_exec(), "emacs-31");
+        assert!(config.start_on_connect());
+        assert!(config.server_socket_dir().ends_with("sockets"));
+    }
+
+    #[test]
+    fn unrelated_env_vars_are_ignored() {
+        let dir = ScratchDir::new("config-env");
+        let file = dir.join("config.toml");
+        fs::write(&file, "").unwrap();
+        let config = load(
+            Some(&file),
+            &[("EUD_FOO", "1"), ("EUD_DEFAULT_SOCKET", "work"), ("EUD_SERVER_SOCKET_DIR", &dir.display().to_string())],
+            &Overrides::default(),
+        ).unwrap();
+
+        assert_eq!(config.default_socket_name(), "work");
+    }
+
+    #[test]
+    fn daemon_profiles() {
+        let dir = ScratchDir::new("config-profiles");
+        let file = dir.join("config.toml");
+        fs::write(&file, concat!(
+            "server_auth_dir = \"~/emacs-auth\"\n",
+            "[daemons.work]\n",
+            "init_directory = \"~/.config/doom-emacs\"\n",
+            "env = { LANG = \"en_GB.UTF-8\" }\n",
+            "autostart = true\n",
+            "\n",
+            "[daemons.notes]\n",
+            "no_init = true\n",
+            "load = [\"~/notes/init.el\"]\n",
+            "tcp = true\n",
+            "port = 4242\n",
+        )).unwrap();
+
+        let config = load(Some(&file), &[], &Overrides::default()).unwrap();
+
+        assert_eq!(config.profiles().keys().collect::<Vec<_>>(), ["notes", "work"]);
+        let work = config.profile("work").unwrap();
+        assert!(work.autostart);
+        assert_eq!(work.env.get("LANG").map(String::as_str), Some("en_GB.UTF-8"));
+        assert!(config.profile("notes").unwrap().no_init);
+        assert_eq!(config.profile("notes").unwrap().port, Some(4242));
+        assert!(config.profile("mail").is_none());
+        assert_eq!(config.server_auth_dir(), &dirs::home_dir().unwrap().join("emacs-auth"));
+
+        fs::write(&file, "[daemons.work]\nauto_start = true\n").unwrap();
+        assert!(load(Some(&file), &[], &Overrides::default()).is_err());
+    }
+
+    #[test]
+    fn unknown_key_is_reported_with_line() {
+        let dir = ScratchDir::new("config-unknown");
+        let file = dir.join("config.toml");
+        fs::write(&file, "default_socket = \"work\"\nsocket_nmae = \"oops\"\n").unwrap();
+
+        let err = load(Some(&file), &[], &Overrides::default()).err().unwrap();
+
+        assert_eq!(err.line, Some(2));
+        assert!(err.to_string().contains(&format!("{}:2", file.display())));
+        assert!(err.to_string().contains("socket_nmae"));
+    }
+
+    #[test]
+    fn type_error_is_reported_with_line() {
+        let dir = ScratchDir::new("config-type");
+        let file = dir.join("config.yaml");
+        fs::write(&file, "default_socket: work\nemacs_exec:\n  - emacs\n").unwrap();
+
+        let err = load(Some(&file), &[], &Overrides::default()).err().unwrap();
+
+        assert_eq!(err.line, Some(2));
+    }
+
+    #[test]
+    fn socket_dir_is_created_when_missing() {
+        let dir = ScratchDir::new("config-create");
+        let sockets = dir.join("not").join("yet");
+
+        let created = create_server_socket_dir(&sockets).unwrap();
+
+        assert!(created.is_dir());
+        assert_eq!(fs::metadata(&created).unwrap().permissions().mode() & 0o777, 0o700);
+    }
+
+    #[test]
+    fn emacs_socket_dir_follows_emacs_resolution() {
+        assert_eq!(
+            emacs_socket_dir(Some("/run/user/1000".into()), Some("/var/tmp".into()), 1000),
+            PathBuf::from("/run/user/1000/emacs"),
+        );
+        assert_eq!(
+            emacs_socket_dir(None, Some("/var/tmp".into()), 1000),
+            PathBuf::from("/var/tmp/emacs1000"),
+        );
+        assert_eq!(
+            emacs_socket_dir(Some("".into()), None, 501),
+            PathBuf::from("/tmp/emacs501"),
+        );
+    }
+
+    #[test]
+    fn explicit_config_file_must_exist() {
+        let missing = env::temp_dir().join("eud-config-does-not-exist.toml");
+        assert!(load(Some(&missing), &[], &Overrides::default()).is_err());
+    }
+}
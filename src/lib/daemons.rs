@@ -1,127 +1,720 @@
+use std::fmt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
 use std::path::{Path, PathBuf};
-use sysinfo::{Pid, Process, ProcessExt, Signal, System, SystemExt, Uid};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use clap::ValueEnum;
+use sysinfo::{Pid, PidExt, Process, ProcessExt, ProcessStatus, Signal, System, SystemExt, Uid};
 use standard_styled::CommandParts;
-use crate::config::Config;
+use crate::client::lisp_string;
+use crate::client::protocol::{self, Command, Server, ServerFile};
+use crate::config::{self, Config, Profile};
+use crate::error::{Error, Result};
+
+/// How long a daemon may go without a socket after it is launched (e.g.
+/// while loading its init file) before it is considered unhealthy.
+const STARTUP_GRACE: Duration = Duration::from_secs(60);
+
+/// How long to wait for a TCP server to accept a connection, when only
+/// checking whether anything is listening.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
 
 #[derive(Clone, Debug)]
 pub struct DaemonProcess {
     pub pid: Pid,
     pub user_id: Option<Uid>,
     pub socket_name: String,
+    /// The process's command line, including the program
+    pub args: Vec<String>,
+    /// Full path to the daemon's socket file, if it could be determined
+    pub socket_path: Option<PathBuf>,
+    /// Full path to the daemon's server file, if it listens on TCP (see
+    /// `Config::server_auth_dir`)
+    pub server_file: Option<PathBuf>,
+    /// Seconds since the Unix epoch at which the process started
+    pub start_time: u64,
+    /// Resident set size, in bytes
+    pub memory: u64,
+    /// Percentage of one CPU used since the last measurement, if measured
+    /// (see `get_all_measured`)
+    pub cpu_usage: Option<f32>,
+    pub cwd: Option<PathBuf>,
+    pub exe: Option<PathBuf>,
+    /// The process's environment, as `KEY=VALUE` entries
+    pub environ: Vec<String>,
+    /// The version of Emacs running the daemon, if known
+    pub version: Option<String>,
+}
+
+/// The ways of stopping a daemon, in order of escalation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Shutdown {
+    /// ask the daemon to save its buffers and exit, over its socket
+    Save,
+    /// send the TERM signal
+    Term,
+    /// send the KILL signal
+    Kill,
+}
+
+impl Shutdown {
+    /// Every stage, from the politest.
+    pub const ALL: [Shutdown; 3] = [Shutdown::Save, Shutdown::Term, Shutdown::Kill];
+}
+
+impl fmt::Display for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shutdown::Save => write!(f, "saved buffers and exited"),
+            Shutdown::Term => write!(f, "terminated by TERM signal"),
+            Shutdown::Kill => write!(f, "killed by KILL signal"),
+        }
+    }
+}
+
+/// The state of a daemon, as found by `DaemonProcess::health`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Health {
+    /// the daemon answered a trivial evaluation
+    Ready,
+    /// the daemon has no socket yet, but was only recently launched
+    Starting,
+    /// the daemon accepted a connection, but did not answer in time
+    Unresponsive,
+    /// no socket file can be found for the daemon
+    SocketMissing,
+    /// the socket file exists, but nothing is listening on it
+    Orphaned,
+    /// the socket path is not a socket, or is not owned by the user
+    Invalid(String),
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Health::Ready => write!(f, "Ready"),
+            Health::Starting => write!(f, "Starting"),
+            Health::Unresponsive => write!(f, "Unresponsive"),
+            Health::SocketMissing => write!(f, "SocketMissing"),
+            Health::Orphaned => write!(f, "Orphaned"),
+            Health::Invalid(reason) => write!(f, "Invalid ({reason})"),
+        }
+    }
 }
 
 impl DaemonProcess {
-    pub(crate) fn from_sys_process(p: &Process) -> Option<Self> {
-        // The socket name needs to be derived from the command arguments
-        // passed to emacs. These will be of the form:
-        // --bg-daemon=\xxx,y\012/name//or/socket/path
-        // The result of `p.cmd()` is therefore parsed to extract the
-        // "/name//or/socket/path" portion into a `Path`, to extract the
-        // socket filename
-        let socket_name = Path::new(p.cmd()
-            .get(1)?
-            .split_once('=')?
-            .1
-            .split('\n')
-            .last()?
-        ).file_name()?.to_str();
+    pub(crate) fn from_sys_process(p: &Process, config: &Config) -> Option<Self> {
+        let server_name = daemon_server_name(p.cmd())?;
+        let socket_name = Path::new(&server_name).file_name()?.to_str()?.to_owned();
+        let socket_path = match Path::new(&server_name).is_absolute() {
+            true => Some(PathBuf::from(&server_name)),
+            // a relative name is resolved by Emacs against its own
+            // `server-socket-dir', which depends on the daemon's
+            // environment; otherwise look in every known socket directory
+            false => {
+                let environ = |var: &str| p.environ()
+                    .iter()
+                    .find_map(|entry| entry.strip_prefix(var)?.strip_prefix('='))
+                    .map(Into::into);
+                let uid = p.user_id().map_or_else(config::current_uid, |uid| **uid);
+                std::iter::once(config::emacs_socket_dir(environ("XDG_RUNTIME_DIR"), environ("TMPDIR"), uid))
+                    .chain(config.socket_search_dirs())
+                    .map(|dir| dir.join(&server_name))
+                    .find(|path| path.exists())
+            }
+        };
+
+        // a TCP server writes its file in `server-auth-dir' instead; its pid
+        // is that within its own PID namespace (e.g. in a container), so a
+        // file with another pid is only trusted if there is no socket, and
+        // something is listening at its address (i.e. it is not left over
+        // from a daemon which crashed)
+        let server_file = Some(config.server_auth_dir().join(&server_name))
+            .filter(|path| match ServerFile::read(path) {
+                Ok(file) if file.pid == Some(p.pid().as_u32()) => true,
+                Ok(file) => socket_path.is_none() && is_listening(&file),
+                Err(_) => false,
+            });
+        let socket_path = socket_path.filter(|_| server_file.is_none());
 
         Some(Self {
             pid: p.pid(),
             user_id: p.user_id().cloned(),
-            socket_name: socket_name?.to_owned(),
+            socket_name,
+            args: p.cmd().to_vec(),
+            socket_path,
+            server_file,
+            start_time: p.start_time(),
+            memory: p.memory(),
+            cpu_usage: None,
+            cwd: non_empty_path(p.cwd()),
+            exe: non_empty_path(p.exe()),
+            environ: p.environ().to_vec(),
+            version: version_from_path(p.exe()),
         })
     }
 
-    pub(crate) fn kill(&self) -> Result<Pid, std::io::Error> {
+    /// Find the version of Emacs running the daemon, if it is not known
+    /// from the path to its executable: first from the executable's
+    /// `--version`, then by asking the daemon itself.
+    pub fn resolve_version(&mut self) {
+        if self.version.is_none() {
+            self.version = self.exe.as_deref()
+                .and_then(version_from_exe)
+                .or_else(|| self.version_from_server(Duration::from_secs(1)));
+        }
+    }
+
+    fn version_from_server(&self, timeout: Duration) -> Option<String> {
+        let server = self.server().ok()?;
+        let response = protocol::request(&server, &[Command::Eval("emacs-version".into())], Some(timeout)).ok()?;
+        let version = response.values.first()?;
+        Some(version.strip_prefix('"')?.strip_suffix('"')?.to_string())
+    }
+
+    /// Check that the daemon's socket exists, is a socket owned by the
+    /// user (or that its server file can be read, if it listens on TCP),
+    /// and that the daemon answers a trivial evaluation within `timeout`.
+    pub fn health(&self, timeout: Duration) -> Health {
+        let server = match self.server() {
+            Ok(server) => server,
+            Err(_) => return match self.age() < STARTUP_GRACE {
+                true => Health::Starting,
+                false => Health::SocketMissing,
+            },
+        };
+        if let Server::Local(socket) = &server {
+            match std::fs::metadata(socket) {
+                Err(_) => return Health::SocketMissing,
+                Ok(meta) if !meta.file_type().is_socket() => {
+                    return Health::Invalid(format!("{} is not a socket", socket.display()))
+                }
+                Ok(meta) if meta.uid() != config::current_uid() => {
+                    return Health::Invalid(format!("{} is owned by uid {}", socket.display(), meta.uid()))
+                }
+                Ok(_) => (),
+            }
+        }
+        let mut connection = match protocol::Connection::connect(&server, Some(timeout)) {
+            Ok(connection) => connection,
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => return Health::Orphaned,
+            Err(_) => return Health::Unresponsive,
+        };
+        let response = connection.send(&[Command::CurrentFrame, Command::Eval("t".into())])
+            .and_then(|_| connection.response());
+        match response {
+            Ok(response) if response.emacs_pid.is_some() => Health::Ready,
+            _ => Health::Unresponsive,
+        }
+    }
+
+    /// Whether the daemon belongs to the user running `eud` (assumed so,
+    /// if its owner is unknown).
+    pub fn is_own(&self) -> bool {
+        self.user_id.as_ref().is_none_or(|uid| **uid == config::current_uid())
+    }
+
+    /// Time since the process started.
+    pub fn age(&self) -> Duration {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        now.saturating_sub(Duration::from_secs(self.start_time))
+    }
+
+    /// Stop the daemon by each of `stages` in turn, until its process has
+    /// exited, waiting up to `grace` after each; returns the stage which
+    /// stopped it, or why it could not be asked if no stage was attempted.
+    pub(crate) fn shut_down(&self, stages: &[Shutdown], grace: Duration) -> Result<Shutdown> {
+        if !self.is_own() {
+            return Err(self.permission_denied());
+        }
+        let mut skipped = None;
+        for &stage in stages {
+            let attempt = match stage {
+                // a failure to ask politely (e.g. the socket is gone) is
+                // not an error, but a reason to escalate
+                Shutdown::Save => match self.ask_to_exit(grace) {
+                    Ok(()) => Ok(()),
+                    Err(e) => {
+                        skipped = Some(e);
+                        continue;
+                    }
+                },
+                // TERM is consistent with `kill PID` on MacOS and allows
+                // the Emacs daemon process to clear up its socket file.
+                Shutdown::Term => self.signal(Signal::Term),
+                Shutdown::Kill => self.signal(Signal::Kill),
+            };
+            match attempt {
+                Err(Error::ProcessNotFound { .. }) => return Ok(stage),
+                Err(e) => return Err(e),
+                Ok(()) if self.wait_for_exit(grace) => return Ok(stage),
+                Ok(()) => skipped = None,
+            }
+        }
+        Err(skipped.unwrap_or_else(|| Error::StillRunning {
+            name: self.socket_name.clone(),
+            pid: self.pid,
+        }))
+    }
+
+    /// Ask the daemon to start its server again (e.g. to recreate a socket
+    /// which has been deleted), by sending it the USR1 signal. Emacs only
+    /// does so if `server-start` is bound to this signal, e.g. with
+    ///   (define-key special-event-map [sigusr1] #'server-start)
+    pub(crate) fn restart_server(&self) -> Result<()> {
+        if !self.is_own() {
+            return Err(self.permission_denied());
+        }
+        self.signal(Signal::User1)
+    }
+
+    /// Ask the daemon to save every file-visiting buffer, then exit (which
+    /// runs `kill-emacs-hook`, e.g. to save the desktop), failing if it
+    /// could not be asked.
+    fn ask_to_exit(&self, timeout: Duration) -> Result<()> {
+        let server = self.server()?;
+        let save_and_exit = "(progn (save-some-buffers t) (kill-emacs))";
+        match protocol::request(&server, &[Command::Eval(save_and_exit.into())], Some(timeout)) {
+            Ok(response) if response.is_error() => Err(Error::Eval {
+                daemon: self.socket_name.clone(),
+                message: response.errors.join("\n"),
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => Err(Error::Connection {
+                socket: server.path().to_path_buf(),
+                source: e,
+            }),
+            // the daemon exits without replying, so a closed connection
+            // is expected here
+            Ok(_) | Err(_) => Ok(()),
+        }
+    }
+
+    fn signal(&self, signal: Signal) -> Result<()> {
         let system = System::new_all();
         let pid = self.pid;
         match system.process(pid) {
-            // Process should be killed with TERM signal (15),
-            // this is consistent with `kill PID` on MacOS and allows
-            // the Emacs daemon process to clear up its socket file.
-            Some(process) => match process.kill_with(Signal::Term) {
-                Some(true) => Ok(pid),
-                Some(false) => Err(
-                    std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        format!(
-                            "Error trying to send kill signal to Emacs daemon '{}' with Pid {}.",
-                            self.socket_name,
-                            pid
-                        )
-                    )
-                ),
-                None => Err(
-                    std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        "Signal::Term does not exist on this system."
-                    )
-                ),
+            Some(process) => match process.kill_with(signal) {
+                Some(true) => Ok(()),
+                Some(false) => Err(Error::SignalFailed {
+                    name: self.socket_name.clone(),
+                    pid,
+                    signal,
+                }),
+                None => Err(Error::SignalUnsupported { signal }),
             },
-            None => Err(
-                std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Error trying to send kill signal to Emacs daemon. No process found with with Pid {}.", pid)
-                )
-            )
+            None => Err(Error::ProcessNotFound { pid }),
         }
     }
 
-    pub(crate) fn show(&self, config: &Config) -> String {
+    /// Wait up to `timeout` for the daemon's process to exit, returning
+    /// whether it has.
+    fn wait_for_exit(&self, timeout: Duration) -> bool {
+        let deadline = std::time::Instant::now() + timeout;
+        let mut system = System::new();
+        loop {
+            // a process which has exited, but is not yet reaped by its
+            // parent, is a zombie
+            let running = system.refresh_process(self.pid)
+                && system.process(self.pid).map(|p| p.status()) != Some(ProcessStatus::Zombie);
+            if !running {
+                return true;
+            }
+            if std::time::Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    pub(crate) fn permission_denied(&self) -> Error {
+        Error::PermissionDenied {
+            name: self.socket_name.clone(),
+            pid: self.pid,
+            uid: self.user_id.as_ref().map(|uid| **uid),
+        }
+    }
+
+    pub(crate) fn show(&self) -> String {
         let socket_name_fmt = format!("{:<14}", self.socket_name);
         let pid_fmt = format!("Pid: {:>8}", format!("{}", self.pid));
+        // the socket may be missing if the daemon is still starting, or if
+        // the socket file has been deleted from under it
         let socket_fmt = format!(
             "Socket: {:<30} ",
-            self.socket_file(config)
-                .expect("problem with socket file...")  // this exception can be reached if an emacs daemon process continues but the socket has been closed... why/how/handle?
-                .to_str()
-                .expect("path has invalid chars")
+            match self.server() {
+                Ok(server) => server.to_string(),
+                Err(_) => "<missing>".to_string(),
+            }
         );
-        format!("{socket_name_fmt} [{pid_fmt}, {socket_fmt}]")
-    }
-
-    pub(crate) fn socket_file(
-        &self,
-        config: &Config
-    ) -> std::io::Result<PathBuf> {
-        let socket_path = config.server_socket_dir().join(&self.socket_name);
-        match socket_path.exists() {
-            true  => Ok(socket_path),
-            false => Err(
-                std::io::Error::new(
-                    std::io::ErrorKind::NotFound,
-                    format!(
-                        "Daemon socket at path {} does not exist.",
-                        socket_path.display()
-                    ),
-                )
-            ),
+        let user_fmt = match (self.is_own(), &self.user_id) {
+            (false, Some(uid)) => format!(" (uid {})", **uid),
+            _ => String::new(),
+        };
+        format!("{socket_name_fmt} [{pid_fmt}, {socket_fmt}]{user_fmt}")
+    }
+
+    /// A row of the table printed by `eud list --long` (see `long_header`).
+    pub(crate) fn show_long(&self) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        format!(
+            "{:<14} {:>8} {:>8} {:>7} {:>6} {:<8} {} {}",
+            self.socket_name,
+            self.pid,
+            human_duration(self.age()),
+            human_bytes(self.memory),
+            optional(self.cpu_usage.map(|cpu| format!("{cpu:.1}%"))),
+            optional(self.version.clone()),
+            optional(self.cwd.as_ref().map(|cwd| cwd.display().to_string())),
+            optional(self.server().ok().map(|server| server.to_string())),
+        )
+    }
+
+    /// Where the daemon's server listens: its socket, if it exists, or
+    /// else the address in its server file.
+    pub(crate) fn server(&self) -> Result<Server> {
+        match (&self.server_file, &self.socket_path) {
+            (Some(path), _) => ServerFile::read(path)
+                .map(Server::Tcp)
+                .map_err(|_| Error::SocketMissing {
+                    name: self.socket_name.clone(),
+                    path: Some(path.clone()),
+                }),
+            (None, Some(socket_path)) if socket_path.exists() => Ok(Server::Local(socket_path.clone())),
+            (None, socket_path) => Err(Error::SocketMissing {
+                name: self.socket_name.clone(),
+                path: socket_path.clone(),
+            }),
         }
     }
 }
 
-pub(crate) fn get_all() -> Vec<DaemonProcess> {
-    System::new_all()
+/// The header of the table printed by `eud list --long`.
+pub(crate) fn long_header() -> String {
+    format!(
+        "{:<14} {:>8} {:>8} {:>7} {:>6} {:<8} {} {}",
+        "NAME", "PID", "UPTIME", "RSS", "CPU", "VERSION", "CWD", "SOCKET"
+    )
+}
+
+/// e.g. `45s`, `12m`, `3h05m`, `2d04h`
+fn human_duration(duration: Duration) -> String {
+    let (days, hours, minutes, seconds) = {
+        let secs = duration.as_secs();
+        (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60, secs % 60)
+    };
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{seconds}s"),
+        (0, 0, _) => format!("{minutes}m"),
+        (0, _, _) => format!("{hours}h{minutes:02}m"),
+        _ => format!("{days}d{hours:02}h"),
+    }
+}
+
+/// e.g. `512K`, `87.3M`, `2.1G`
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{size:.0}{}", UNITS[unit]),
+        _ => format!("{size:.1}{}", UNITS[unit]),
+    }
+}
+
+/// `sysinfo` gives an empty path for information it could not read.
+fn non_empty_path(path: &Path) -> Option<PathBuf> {
+    (!path.as_os_str().is_empty()).then(|| path.to_path_buf())
+}
+
+/// Find the version of Emacs from the path to its executable, which
+/// commonly includes it, e.g. `/usr/bin/emacs-29.3` or
+/// `/nix/store/...-emacs-29.3/bin/emacs`.
+fn version_from_path(path: &Path) -> Option<String> {
+    path.iter()
+        .rev()
+        .filter_map(|component| component.to_str())
+        .find_map(|component| {
+            let lower = component.to_lowercase();
+            let start = lower.find("emacs-")? + "emacs-".len();
+            let version: String = component[start..]
+                .chars()
+                .take_while(|c| c.is_ascii_digit() || *c == '.')
+                .collect();
+            let version = version.trim_end_matches('.');
+            version.contains('.').then(|| version.to_string())
+        })
+}
+
+/// Find the version of Emacs from the output of `EXE --version`, whose
+/// first line is e.g. `GNU Emacs 29.3`.
+fn version_from_exe(exe: &Path) -> Option<String> {
+    let output = std::process::Command::new(exe)
+        .arg("--version")
+        .stdin(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout.lines().next()?.strip_prefix("GNU Emacs ")?;
+    Some(version.trim().to_string())
+}
+
+/// Extract the server name (a socket name, or a full path to a socket)
+/// from the arguments of an Emacs daemon process. These take any of the
+/// forms:
+///   --daemon, --daemon=name, --fg-daemon=/path/to/name,
+///   --bg-daemon=\xxx,y\012/name//or/socket/path
+/// where the latter is how Emacs re-executes itself when daemonising.
+/// A daemon started without a name uses the name "server".
+fn daemon_server_name(args: &[String]) -> Option<String> {
+    args.iter().skip(1).find_map(|arg| {
+        // only an option (not e.g. a file named `daemon`) names the server
+        let arg = arg.strip_prefix("--").or_else(|| arg.strip_prefix('-'))?;
+        let (flag, value) = arg.split_once('=').unwrap_or((arg, ""));
+        match flag {
+            "daemon" | "fg-daemon" => Some(value),
+            // the file descriptors passed to the child precede the name
+            "bg-daemon" => Some(value.split('\n').nth(1).unwrap_or("")),
+            _ => None,
+        }
+    })
+    .map(|name| match name.is_empty() {
+        true => "server".to_string(),
+        false => name.to_string(),
+    })
+}
+
+/// Every daemon belonging to the user running `eud`.
+pub(crate) fn get_all(config: &Config) -> Vec<DaemonProcess> {
+    daemons_of(&System::new_all(), false, config)
+}
+
+/// Every daemon on the system, whichever user it belongs to.
+pub(crate) fn get_all_users(config: &Config) -> Vec<DaemonProcess> {
+    daemons_of(&System::new_all(), true, config)
+}
+
+/// As `get_all` (or `get_all_users`), but with the CPU usage of each
+/// daemon measured (which takes at least
+/// `System::MINIMUM_CPU_UPDATE_INTERVAL`) and its version resolved (see
+/// `DaemonProcess::resolve_version`).
+pub(crate) fn get_all_measured(all_users: bool, config: &Config) -> Vec<DaemonProcess> {
+    let mut system = System::new_all();
+    std::thread::sleep(System::MINIMUM_CPU_UPDATE_INTERVAL);
+    system.refresh_processes();
+    let mut daemons = daemons_of(&system, all_users, config);
+    for daemon in daemons.iter_mut() {
+        daemon.cpu_usage = system.process(daemon.pid).map(|p| p.cpu_usage());
+        daemon.resolve_version();
+    }
+    daemons
+}
+
+fn daemons_of(system: &System, all_users: bool, config: &Config) -> Vec<DaemonProcess> {
+    system
         .processes()
-        .iter()
-        .map(|(_, p)| p)
+        .values()
         .filter(|p| p.name().to_lowercase().starts_with("emacs"))
-        .filter(|p| p.cmd().get(1)
-            .map_or_else(
-                || false,
-                |args| args.contains("daemon"))
-        )
-        .filter_map(DaemonProcess::from_sys_process)
+        .filter_map(|p| DaemonProcess::from_sys_process(p, config))
+        .filter(|daemon| all_users || daemon.is_own())
         .collect()
 }
 
-pub(crate) fn active_daemons_names() -> Vec<String> {
-    get_all().iter().map(|d| d.socket_name.clone()).collect()
+pub(crate) fn active_daemons_names(config: &Config) -> Vec<String> {
+    get_all(config).iter().map(|d| d.socket_name.clone()).collect()
+}
+
+/// Socket files in any of the directories in which a daemon's socket may
+/// be found on which nothing is listening, e.g. those left behind by a
+/// daemon which crashed.
+pub(crate) fn stale_sockets(config: &Config) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = config
+        .socket_search_dirs()
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    paths.sort();
+    paths.dedup();
+    paths.into_iter().filter(|path| is_stale_socket(path)).collect()
+}
+
+/// Whether `socket` is in a directory used only for Emacs' sockets (so
+/// that it is safe to remove when stale): `eud`'s socket directory, as
+/// configured, or one laid out as Emacs' `server-socket-dir' is, i.e.
+/// named `emacs` or `emacs$UID`. A directory shared with other programs,
+/// e.g. `$XDG_RUNTIME_DIR` itself, is not.
+pub(crate) fn in_emacs_socket_dir(socket: &Path, config: &Config) -> bool {
+    let Some(dir) = socket.parent() else {
+        return false;
+    };
+    let name = dir.file_name().and_then(|name| name.to_str());
+    name == Some("emacs")
+        || name == Some(&format!("emacs{}", config::current_uid()))
+        // `server_socket_dir` is canonical already
+        || std::fs::canonicalize(dir).is_ok_and(|dir| &dir == config.server_socket_dir())
+}
+
+/// Server files in `Config::server_auth_dir` whose address refuses
+/// connections, e.g. those left behind by a TCP daemon which crashed.
+pub(crate) fn stale_server_files(config: &Config) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(config.server_auth_dir()) else {
+        return vec![];
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| match ServerFile::read(path) {
+            Ok(file) => matches!(
+                protocol::Connection::connect(&Server::Tcp(file), Some(PROBE_TIMEOUT)),
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused
+            ),
+            Err(_) => false,
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Whether a connection to the address in `file` is accepted.
+fn is_listening(file: &ServerFile) -> bool {
+    protocol::Connection::connect(&Server::Tcp(file.clone()), Some(PROBE_TIMEOUT)).is_ok()
+}
+
+/// Whether `path` is a socket, owned by the user, to which a connection
+/// is refused.
+fn is_stale_socket(path: &Path) -> bool {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() && meta.uid() == config::current_uid() => matches!(
+            protocol::Connection::open(&Server::Local(path.to_path_buf())),
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused
+        ),
+        _ => false,
+    }
+}
+
+/// The user's daemons with no socket on which they are listening (but
+/// not those still starting), with their health.
+pub(crate) fn orphaned(config: &Config) -> Vec<(DaemonProcess, Health)> {
+    get_all(config)
+        .into_iter()
+        .map(|daemon| {
+            let health = daemon.health(Duration::from_secs(1));
+            (daemon, health)
+        })
+        .filter(|(_, health)| matches!(health, Health::SocketMissing | Health::Orphaned))
+        .collect()
+}
+
+/// Wait for the daemon named `name` to be ready for clients, for up to
+/// `timeout`, returning its last known health, or `None` as soon as no
+/// daemon process with that name is running (as its launcher has already
+/// exited, the daemon would have been forked by then).
+pub(crate) fn wait_until_ready(
+    name: &str,
+    timeout: Duration,
+    config: &Config,
+) -> Option<Health> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        let health = get_all(config)
+            .into_iter()
+            .find(|daemon| daemon.socket_name == name)
+            .map(|daemon| daemon.health(Duration::from_secs(1)));
+        if matches!(health, Some(Health::Ready) | None) || std::time::Instant::now() >= deadline {
+            return health;
+        }
+        std::thread::sleep(Duration::from_millis(250));
+    }
+}
+
+/// Options with which to launch a new daemon, beyond its name.
+#[derive(Clone, Debug, Default)]
+pub struct LaunchOptions {
+    /// Emacs executable, in place of the configured `emacs_exec`
+    pub emacs: Option<String>,
+    /// `--init-directory`, in place of `~/.emacs.d` (Emacs 29 or later)
+    pub init_directory: Option<PathBuf>,
+    /// `-Q`: load no init files
+    pub no_init: bool,
+    /// files to `--load`, in order
+    pub load: Vec<PathBuf>,
+    /// expressions to `--eval`, in order (after loading files)
+    pub eval: Vec<String>,
+    /// working directory of the daemon, if not the current one
+    pub cwd: Option<PathBuf>,
+    /// environment variables set for the daemon
+    pub env: Vec<(String, String)>,
+    /// any other arguments to Emacs, passed last
+    pub args: Vec<String>,
+    /// a command which runs Emacs (and its arguments, which follow), e.g.
+    /// `["direnv", "exec", "/path/to/project"]`
+    pub wrapper: Vec<String>,
+    /// listen on TCP, rather than a local socket
+    pub tcp: Option<TcpOptions>,
+}
+
+/// Where a daemon's server listens on TCP, rather than on a local socket
+/// (Emacs' `server-use-tcp'); Emacs chooses whichever is not given.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TcpOptions {
+    /// `server-host', e.g. `0.0.0.0` to listen beyond this host (by
+    /// default, `127.0.0.1`)
+    pub host: Option<String>,
+    /// `server-port' (by default, any free port)
+    pub port: Option<u16>,
+}
+
+impl From<&Profile> for LaunchOptions {
+    fn from(profile: &Profile) -> Self {
+        let expand = |path: &PathBuf| config::expand_tilde_as_home(path).into_owned();
+        Self {
+            emacs: profile.emacs.clone(),
+            init_directory: profile.init_directory.as_ref().map(expand),
+            no_init: profile.no_init,
+            load: profile.load.iter().map(expand).collect(),
+            eval: profile.eval.clone(),
+            cwd: profile.cwd.as_ref().map(expand),
+            env: profile.env.clone().into_iter().collect(),
+            args: profile.args.clone(),
+            wrapper: vec![],
+            tcp: (profile.tcp || profile.host.is_some() || profile.port.is_some()).then(|| TcpOptions {
+                host: profile.host.clone(),
+                port: profile.port,
+            }),
+        }
+    }
+}
+
+impl LaunchOptions {
+    /// These options, with those of `other` taking precedence: its
+    /// executable and directories replace these, and its files,
+    /// expressions, environment and arguments follow these.
+    pub fn merge(mut self, other: LaunchOptions) -> Self {
+        self.emacs = other.emacs.or(self.emacs);
+        self.init_directory = other.init_directory.or(self.init_directory);
+        self.no_init |= other.no_init;
+        self.load.extend(other.load);
+        self.eval.extend(other.eval);
+        self.cwd = other.cwd.or(self.cwd);
+        self.env.extend(other.env);
+        self.args.extend(other.args);
+        if !other.wrapper.is_empty() {
+            self.wrapper = other.wrapper;
+        }
+        self.tcp = other.tcp.or(self.tcp);
+        self
+    }
 }
 
 pub(crate) fn build_new(
     name: Option<String>,
+    options: &LaunchOptions,
     config: &Config
 ) -> CommandParts {
     let daemon_name = match name {
@@ -130,29 +723,471 @@ pub(crate) fn build_new(
     };
     {
         // "/nix/store/2ald91hw1y9dbwwrc6757mnq9i5i99p0-emacs-29.3/Applications/Emacs.app/Contents/MacOS/Emacs"
-        let program = config.emacs_exec();
-        let args = &vec![format!("--daemon={}", daemon_name)];
-        CommandParts {
-            program: program.into(),
-            args: args.clone(),
+        let program = options.emacs.as_ref().unwrap_or(config.emacs_exec());
+        // the socket is given as a full path, so that Emacs need not be
+        // configured with eud's `server-socket-dir'; a TCP server's file
+        // is named for the daemon in eud's `server-auth-dir'
+        let socket = match options.tcp {
+            Some(_) => PathBuf::from(&daemon_name),
+            None => config.server_socket_dir().join(&daemon_name),
+        };
+        // these are "initial options", which Emacs requires before others
+        let mut args = vec![format!("--daemon={}", socket.display())];
+        if let Some(dir) = &options.init_directory {
+            args.push(format!("--init-directory={}", dir.display()));
+        }
+        if options.no_init {
+            args.push("-Q".to_string());
+        }
+        // the server is started after every `--eval`, and the init file
+        if let Some(tcp) = &options.tcp {
+            args.push(format!("--eval={}", tcp_settings(tcp, config)));
         }
+        for file in &options.load {
+            args.push(format!("--load={}", file.display()));
+        }
+        for expr in &options.eval {
+            args.push(format!("--eval={expr}"));
+        }
+        args.extend(options.args.iter().cloned());
+        let mut command = match options.wrapper.split_first() {
+            Some((wrapper, wrapper_args)) => CommandParts::new(
+                wrapper,
+                &[wrapper_args, std::slice::from_ref(program), &args].concat(),
+            ),
+            None => CommandParts::new(program, &args),
+        };
+        command.cwd = options.cwd.clone();
+        command.env = options.env.clone();
+        command
     }
 }
-// TODO: (above) look into std::process::Command::{current_dir, envs}
 
-pub(crate) fn kill_by_name(name: &str) -> Result<Pid, std::io::Error> {
-    get_all()
+/// An expression setting up a daemon's server to listen on TCP.
+fn tcp_settings(tcp: &TcpOptions, config: &Config) -> String {
+    let mut settings = vec![
+        "server-use-tcp t".to_string(),
+        format!("server-auth-dir {}", lisp_string(&format!("{}/", config.server_auth_dir().display()))),
+    ];
+    if let Some(host) = &tcp.host {
+        settings.push(format!("server-host {}", lisp_string(host)));
+    }
+    if let Some(port) = tcp.port {
+        settings.push(format!("server-port {port}"));
+    }
+    format!("(setq {})", settings.join(" "))
+}
+
+/// The command to launch `daemon` again as it was launched: with the
+/// same program, arguments, working directory and environment. If its
+/// command line is unknown, it is launched as by `build_new`.
+pub(crate) fn build_restart(daemon: &DaemonProcess, config: &Config) -> CommandParts {
+    let Some(program) = daemon.args.first() else {
+        return build_new(Some(daemon.socket_name.clone()), &LaunchOptions::default(), config);
+    };
+    // prefer the program as invoked (e.g. `~/.nix-profile/bin/emacs`,
+    // which may since have been upgraded) to the executable it resolved
+    // to, unless it cannot be found again: a bare name is looked up in
+    // the daemon's `PATH`, if known, but a relative path depends on how
+    // the daemon was launched
+    let found_again = match program.contains('/') {
+        true => Path::new(program).is_absolute(),
+        false => !daemon.environ.is_empty(),
+    };
+    let program = match (found_again, &daemon.exe) {
+        (false, Some(exe)) => exe.display().to_string(),
+        _ => program.clone(),
+    };
+    let mut command = CommandParts::new(&program, &relaunch_args(&daemon.args));
+    command.cwd = daemon.cwd.clone();
+    command.env = daemon.environ
         .iter()
-        .find(|&p| p.socket_name == name)
-        .map_or_else(
-            || Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("No Emacs daemon found with socket name {name}"),
-            )),
-            |daemon| daemon.kill(),
-        )
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+    command.clear_env = !command.env.is_empty();
+    command
 }
 
-pub(crate) fn kill_all() -> Vec<Result<Pid, std::io::Error>> {
-    get_all().iter().map(|daemon| daemon.kill()).collect()
+/// The arguments (without the program) with which to relaunch a daemon
+/// which was launched with `args`. Emacs re-executes itself with
+/// `--bg-daemon` when daemonising, and a `--fg-daemon` would not return,
+/// so either becomes `--daemon`.
+fn relaunch_args(args: &[String]) -> Vec<String> {
+    args.iter()
+        .skip(1)
+        .map(|arg| {
+            let Some(flag) = arg.strip_prefix("--").or_else(|| arg.strip_prefix('-')) else {
+                return arg.clone();
+            };
+            let (flag, value) = flag.split_once('=').unwrap_or((flag, ""));
+            let name = match flag {
+                "fg-daemon" => value,
+                "bg-daemon" => value.split('\n').nth(1).unwrap_or(""),
+                _ => return arg.clone(),
+            };
+            match name.is_empty() {
+                true => "--daemon".to_string(),
+                false => format!("--daemon={name}"),
+            }
+        })
+        .collect()
+}
+
+/// Wait up to `timeout` for the socket (or server file) of a daemon which
+/// has exited to be removed. A daemon stopped by a signal may not remove
+/// its socket, so it is removed here if it remains.
+pub(crate) fn await_socket_removal(daemon: &DaemonProcess, timeout: Duration) -> Result<()> {
+    let Some(socket) = daemon.socket_path.as_ref().or(daemon.server_file.as_ref()) else {
+        return Ok(());
+    };
+    let deadline = std::time::Instant::now() + timeout;
+    while socket.exists() && std::time::Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    match socket.exists() {
+        true => Ok(std::fs::remove_file(socket)?),
+        false => Ok(()),
+    }
+}
+
+/// The user's running daemon with socket name `name`. If only another
+/// user has a daemon of that name, permission to use it is denied.
+pub(crate) fn find_by_name(name: &str, config: &Config) -> Result<DaemonProcess> {
+    let (daemons, others): (Vec<DaemonProcess>, Vec<DaemonProcess>) = get_all_users(config)
+        .into_iter()
+        .partition(|daemon| daemon.is_own());
+    match daemons.iter().position(|p| p.socket_name == name) {
+        Some(index) => Ok(daemons[index].clone()),
+        None => match others.iter().find(|p| p.socket_name == name) {
+            Some(other) => Err(other.permission_denied()),
+            None => Err(Error::DaemonNotFound {
+                name: name.to_string(),
+                available: daemons,
+            }),
+        },
+    }
+}
+
+pub(crate) fn kill_by_name(
+    name: &str,
+    stages: &[Shutdown],
+    grace: Duration,
+    config: &Config,
+) -> Result<(Pid, Shutdown)> {
+    let daemon = find_by_name(name, config)?;
+    daemon.shut_down(stages, grace).map(|stage| (daemon.pid, stage))
+}
+
+pub(crate) fn kill_all(
+    stages: &[Shutdown],
+    grace: Duration,
+    config: &Config,
+) -> Vec<Result<(Pid, Shutdown)>> {
+    get_all(config)
+        .iter()
+        .map(|daemon| daemon.shut_down(stages, grace).map(|stage| (daemon.pid, stage)))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, ScratchDir};
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+
+    fn daemon(socket_path: Option<PathBuf>, age: Duration) -> DaemonProcess {
+        let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap() - age;
+        DaemonProcess {
+            pid: Pid::from(1),
+            user_id: None,
+            socket_name: "test".into(),
+            args: vec![],
+            socket_path,
+            server_file: None,
+            start_time: start.as_secs(),
+            memory: 0,
+            cpu_usage: None,
+            cwd: None,
+            exe: None,
+            environ: vec![],
+            version: None,
+        }
+    }
+
+    #[test]
+    fn daemons_of_other_users() {
+        let mut other = daemon(None, Duration::ZERO);
+        other.user_id = Uid::try_from(config::current_uid() as usize + 1).ok();
+        assert!(daemon(None, Duration::ZERO).is_own());
+        assert!(!other.is_own());
+        assert!(matches!(
+            other.shut_down(&Shutdown::ALL, Duration::ZERO),
+            Err(Error::PermissionDenied { .. })
+        ));
+        assert!(other.show().ends_with(&format!("(uid {})", config::current_uid() + 1)));
+    }
+
+    #[test]
+    fn shut_down_escalates() {
+        let mut child = std::process::Command::new("sleep").arg("30").spawn().unwrap();
+        let mut sleeper = daemon(None, Duration::ZERO);
+        sleeper.pid = Pid::from(child.id() as usize);
+        // reap the child, so that it does not linger as a zombie
+        let reaper = std::thread::spawn(move || child.wait().unwrap());
+
+        // without a socket, `sleep` cannot be asked to exit
+        assert!(matches!(
+            sleeper.shut_down(&[Shutdown::Save], Duration::from_secs(2)),
+            Err(Error::SocketMissing { .. })
+        ));
+        assert_eq!(sleeper.shut_down(&Shutdown::ALL, Duration::from_secs(2)).unwrap(), Shutdown::Term);
+        assert!(!reaper.join().unwrap().success());
+    }
+
+    #[test]
+    fn health_without_socket() {
+        assert_eq!(daemon(None, Duration::from_secs(1)).health(Duration::from_secs(1)), Health::Starting);
+        assert_eq!(daemon(None, Duration::from_secs(600)).health(Duration::from_secs(1)), Health::SocketMissing);
+    }
+
+    #[test]
+    fn stop_waiting_once_no_daemon_is_running() {
+        let dir = ScratchDir::new("daemons-gone");
+        let config = testing::config(&dir, "");
+        let start = std::time::Instant::now();
+
+        assert_eq!(wait_until_ready("eud-test-no-such-daemon", Duration::from_secs(30), &config), None);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn health_of_socket_files() {
+        let dir = ScratchDir::new("daemons-health");
+        let not_socket = dir.join("file");
+        std::fs::write(&not_socket, "").unwrap();
+        let orphaned = dir.join("orphaned");
+        drop(UnixListener::bind(&orphaned).unwrap());
+        let old = Duration::from_secs(600);
+
+        assert!(matches!(daemon(Some(not_socket), old).health(Duration::from_secs(1)), Health::Invalid(_)));
+        assert_eq!(daemon(Some(orphaned), old).health(Duration::from_secs(1)), Health::Orphaned);
+    }
+
+    #[test]
+    fn stale_socket_files() {
+        let dir = ScratchDir::new("daemons-stale");
+        let stale = dir.join("stale");
+        drop(UnixListener::bind(&stale).unwrap());
+        let live = dir.join("live");
+        let _listener = UnixListener::bind(&live).unwrap();
+        let file = dir.join("file");
+        std::fs::write(&file, "").unwrap();
+
+        assert!(is_stale_socket(&stale));
+        assert!(!is_stale_socket(&live));
+        assert!(!is_stale_socket(&file));
+        assert!(!is_stale_socket(&dir.join("missing")));
+    }
+
+    #[test]
+    fn emacs_socket_dirs() {
+        let dir = ScratchDir::new("daemons-socket-dirs");
+        let config = testing::config(&dir, "");
+        let uid = config::current_uid();
+        assert!(in_emacs_socket_dir(Path::new("/run/user/1000/emacs/server"), &config));
+        assert!(in_emacs_socket_dir(&PathBuf::from(format!("/tmp/emacs{uid}/work")), &config));
+        assert!(in_emacs_socket_dir(&config.server_socket_dir().join("work"), &config));
+        assert!(in_emacs_socket_dir(&dir.join("sockets").join("work"), &config));
+        assert!(!in_emacs_socket_dir(&dir.join("work"), &config));
+        assert!(!in_emacs_socket_dir(Path::new("/run/user/1000/bus"), &config));
+        assert!(!in_emacs_socket_dir(&PathBuf::from(format!("/tmp/emacs{}/work", uid + 1)), &config));
+    }
+
+    #[test]
+    fn health_of_listening_daemon() {
+        let dir = ScratchDir::new("daemons-listening");
+        let socket = dir.join("server");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = std::thread::spawn(move || {
+            // answer the first connection, then accept but ignore the second
+            let (mut stream, _) = listener.accept().unwrap();
+            BufReader::new(stream.try_clone().unwrap()).read_line(&mut String::new()).unwrap();
+            stream.write_all(b"-emacs-pid 1\n-print t\n").unwrap();
+            drop(stream);
+            listener.accept().unwrap()
+        });
+
+        assert_eq!(daemon(Some(socket.clone()), Duration::ZERO).health(Duration::from_secs(1)), Health::Ready);
+        assert_eq!(daemon(Some(socket), Duration::ZERO).health(Duration::from_millis(50)), Health::Unresponsive);
+        drop(server.join().unwrap());
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn server_name_from_daemon_args() {
+        assert_eq!(daemon_server_name(&args(&["emacs", "--daemon=work"])), Some("work".into()));
+        assert_eq!(daemon_server_name(&args(&["emacs", "--daemon"])), Some("server".into()));
+        assert_eq!(
+            daemon_server_name(&args(&["emacs", "-Q", "--fg-daemon=/run/user/1000/emacs/notes"])),
+            Some("/run/user/1000/emacs/notes".into()),
+        );
+        assert_eq!(
+            daemon_server_name(&args(&["emacs", "--bg-daemon=3,4\n/home/me/.emacs.d/sockets/mail"])),
+            Some("/home/me/.emacs.d/sockets/mail".into()),
+        );
+        assert_eq!(daemon_server_name(&args(&["emacs", "--bg-daemon=3,4\n"])), Some("server".into()));
+        assert_eq!(daemon_server_name(&args(&["emacs", "-daemon=notes"])), Some("notes".into()));
+        assert_eq!(daemon_server_name(&args(&["emacs", "daemon"])), None);
+        assert_eq!(daemon_server_name(&args(&["emacs", "fg-daemon=work"])), None);
+    }
+
+    #[test]
+    fn launch_options_as_emacs_args() {
+        let dir = ScratchDir::new("daemons-launch");
+        let config = testing::config(&dir, "");
+        let options = LaunchOptions {
+            no_init: true,
+            load: vec!["/tmp/test-init.el".into()],
+            eval: vec!["(setq inhibit-startup-screen t)".into()],
+            cwd: Some("/tmp".into()),
+            env: vec![("LANG".into(), "C".into())],
+            args: vec!["--debug-init".into()],
+            ..Default::default()
+        };
+        let command = build_new(Some("vanilla".into()), &options, &config);
+
+        assert_eq!(command.args[1..], args(&[
+            "-Q",
+            "--load=/tmp/test-init.el",
+            "--eval=(setq inhibit-startup-screen t)",
+            "--debug-init",
+        ]));
+        assert!(command.args[0].starts_with("--daemon=") && command.args[0].ends_with("/vanilla"));
+        assert_eq!(command.cwd, Some("/tmp".into()));
+        assert_eq!(command.env, vec![("LANG".to_string(), "C".to_string())]);
+    }
+
+    #[test]
+    fn tcp_daemons() {
+        let dir = ScratchDir::new("daemons-tcp");
+        let config = testing::config(&dir, "");
+        let profile = Profile { port: Some(4242), ..Default::default() };
+        let command = build_new(Some("work".into()), &LaunchOptions::from(&profile), &config);
+        assert_eq!(command.args, vec![
+            "--daemon=work".to_string(),
+            format!(
+                "--eval=(setq server-use-tcp t server-auth-dir \"{}/\" server-port 4242)",
+                config.server_auth_dir().display()
+            ),
+        ]);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_file = dir.join("work");
+        std::fs::write(&server_file, format!("{} 1\nsecret", listener.local_addr().unwrap())).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            BufReader::new(stream.try_clone().unwrap()).read_line(&mut request).unwrap();
+            stream.write_all(b"-emacs-pid 1\n-print t\n").unwrap();
+            request
+        });
+        let mut work = daemon(None, Duration::ZERO);
+        work.server_file = Some(server_file.clone());
+
+        assert!(matches!(work.server(), Ok(Server::Tcp(file)) if file.key == "secret"));
+        assert_eq!(work.health(Duration::from_secs(1)), Health::Ready);
+        assert!(server.join().unwrap().starts_with("-auth secret "));
+        std::fs::remove_file(server_file).unwrap();
+        assert!(matches!(work.server(), Err(Error::SocketMissing { .. })));
+    }
+
+    #[test]
+    fn stale_server_files_refuse_connections() {
+        let dir = ScratchDir::new("daemons-server-files");
+        let config = testing::config(&dir, "");
+        std::fs::create_dir_all(config.server_auth_dir()).unwrap();
+        let live = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stale = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let write = |name: &str, address: std::net::SocketAddr| {
+            let path = config.server_auth_dir().join(name);
+            std::fs::write(&path, format!("{address} 1\nkey")).unwrap();
+            path
+        };
+        let live_file = write("live", live.local_addr().unwrap());
+        let stale_file = write("stale", stale);
+        std::fs::write(config.server_auth_dir().join("other"), "not a server file").unwrap();
+
+        assert!(is_listening(&ServerFile::read(&live_file).unwrap()));
+        assert!(!is_listening(&ServerFile::read(&stale_file).unwrap()));
+        assert_eq!(stale_server_files(&config), vec![stale_file]);
+    }
+
+    #[test]
+    fn launch_options_from_profile_and_arguments() {
+        let profile = Profile {
+            emacs: Some("emacs-29".into()),
+            cwd: Some("~/notes".into()),
+            eval: vec!["(org-agenda-list)".into()],
+            env: [("LANG".to_string(), "C".to_string())].into(),
+            ..Default::default()
+        };
+        let options = LaunchOptions::from(&profile).merge(LaunchOptions {
+            cwd: Some("/tmp".into()),
+            eval: vec!["(message \"hi\")".into()],
+            no_init: true,
+            ..Default::default()
+        });
+
+        assert_eq!(options.emacs.as_deref(), Some("emacs-29"));
+        assert_eq!(options.cwd, Some("/tmp".into()));
+        assert_eq!(options.eval, args(&["(org-agenda-list)", "(message \"hi\")"]));
+        assert!(options.no_init);
+        assert_eq!(options.env, vec![("LANG".to_string(), "C".to_string())]);
+        assert!(!LaunchOptions::from(&profile).cwd.unwrap().starts_with("~"));
+    }
+
+    #[test]
+    fn relaunch_args_use_daemon_flag() {
+        assert_eq!(
+            relaunch_args(&args(&["emacs", "--bg-daemon=3,4\n/home/me/.emacs.d/sockets/mail", "-Q"])),
+            args(&["--daemon=/home/me/.emacs.d/sockets/mail", "-Q"]),
+        );
+        assert_eq!(relaunch_args(&args(&["emacs", "--fg-daemon"])), args(&["--daemon"]));
+        assert_eq!(relaunch_args(&args(&["emacs", "--daemon", "fg-daemon"])), args(&["--daemon", "fg-daemon"]));
+        assert_eq!(
+            relaunch_args(&args(&["emacs", "--daemon=work", "--init-directory=~/.config/emacs"])),
+            args(&["--daemon=work", "--init-directory=~/.config/emacs"]),
+        );
+    }
+
+    #[test]
+    fn version_from_executable_path() {
+        assert_eq!(version_from_path(Path::new("/usr/bin/emacs-29.3")), Some("29.3".into()));
+        assert_eq!(
+            version_from_path(Path::new("/nix/store/2ald91hw1y9dbwwrc6757mnq9i5i99p0-emacs-29.3/Applications/Emacs.app/Contents/MacOS/Emacs")),
+            Some("29.3".into()),
+        );
+        assert_eq!(version_from_path(Path::new("/usr/bin/emacs-gtk")), None);
+    }
+
+    #[test]
+    fn human_readable_sizes_and_durations() {
+        assert_eq!(human_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(human_duration(Duration::from_secs(3 * 3600 + 5 * 60 + 7)), "3h05m");
+        assert_eq!(human_duration(Duration::from_secs(2 * 86_400 + 4 * 3600)), "2d04h");
+        assert_eq!(human_bytes(512 * 1024), "512K");
+        assert_eq!(human_bytes(2_254_857_830), "2.1G");
+    }
+
+    #[test]
+    fn no_server_name_without_daemon_args() {
+        assert_eq!(daemon_server_name(&args(&["emacs", "notes.org"])), None);
+        assert_eq!(daemon_server_name(&args(&["emacsclient", "--socket-name=daemon"])), None);
+    }
 }
@@ -0,0 +1,266 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
+use sysinfo::{Pid, Signal};
+use crate::config::ConfigError;
+use crate::daemons::{DaemonProcess, Health};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The exit status of the `eud` binary for each kind of outcome. These
+/// are stable, so that scripts may rely on them (see the ReadMe).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    /// any failure not covered below
+    Failure = 1,
+    /// invalid arguments or configuration
+    Usage = 2,
+    DaemonNotFound = 3,
+    DaemonAlreadyRunning = 4,
+    /// a daemon was not ready, or did not answer, in time, or did not exit
+    /// when killed
+    Timeout = 5,
+    /// a client could not be started, could not connect, or failed
+    ClientFailed = 6,
+    /// an expression evaluated in a daemon signalled an error
+    EvalFailed = 7,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// no running daemon has the socket name `name`
+    DaemonNotFound { name: String, available: Vec<DaemonProcess> },
+    /// a daemon with the socket name `name` is already running
+    DaemonAlreadyRunning { name: String },
+    /// the daemon's socket file does not exist (or could not be found, if
+    /// `path` is `None`)
+    SocketMissing { name: String, path: Option<PathBuf> },
+    /// a file to be visited by a client does not exist
+    FileNotFound { path: PathBuf },
+    /// the daemon belongs to another user, with uid `uid`
+    PermissionDenied { name: String, pid: Pid, uid: Option<u32> },
+    /// the daemon's process did not exit, however it was asked to stop
+    StillRunning { name: String, pid: Pid },
+    /// the daemon's process no longer exists
+    ProcessNotFound { pid: Pid },
+    /// `signal` could not be sent to the daemon's process
+    SignalFailed { name: String, pid: Pid, signal: Signal },
+    /// `signal` is not supported on this system
+    SignalUnsupported { signal: Signal },
+    /// the client (i.e. `emacsclient`) could not be started
+    ClientSpawn(std::io::Error),
+    /// the client exited unsuccessfully
+    ClientFailed { status: ExitStatus },
+    /// the daemon's server could not carry out a client's request, e.g. to
+    /// visit a file, or the files were abandoned (`server-edit-abort`)
+    ClientRequest { daemon: String, message: String },
+    /// the daemon cannot create a graphical frame (e.g. as it was built
+    /// without support for any window system, or there is no display)
+    WindowSystemUnsupported { daemon: String },
+    /// communicating with the daemon's server over its socket failed
+    Connection { socket: PathBuf, source: std::io::Error },
+    /// the daemon signalled an error while evaluating an expression
+    Eval { daemon: String, message: String },
+    /// Emacs exited unsuccessfully (or the daemon's process went away,
+    /// if `status` is `None`) while launching a daemon
+    Launch { name: String, status: Option<ExitStatus>, stderr_tail: Vec<String> },
+    /// the daemon was not ready for clients within `timeout`
+    NotReady { name: String, timeout: Duration, health: Health },
+    /// the daemon is not ready for clients
+    Unhealthy { name: String, health: Health },
+    /// the configuration could not be loaded
+    Config(ConfigError),
+    /// an argument was not valid, for reasons `clap` cannot determine
+    InvalidArgument(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DaemonNotFound { name, available } => match available.len() {
+                0 => write!(f, "No Emacs daemons are currently running."),
+                _ => write!(
+                    f,
+                    "Emacs daemon named `{}` does not exist.\nActive daemons are:\n{}",
+                    name,
+                    available
+                        .iter()
+                        .map(|d| d.show())
+                        .collect::<Vec<String>>()
+                        .join("\n"),
+                ),
+            },
+            Error::DaemonAlreadyRunning { name } => write!(
+                f,
+                concat!(
+                    "A daemon with name '{name}' is already running. ",
+                    "If you wish to connect to it, try:\n",
+                    "    `eud connect {name} [FILE]`",
+                ),
+                name = name
+            ),
+            Error::SocketMissing { path: Some(path), .. } => write!(
+                f, "Daemon socket at path {} does not exist.", path.display()
+            ),
+            Error::SocketMissing { name, path: None } => write!(
+                f, "No socket found for Emacs daemon '{name}'."
+            ),
+            Error::FileNotFound { path } => write!(
+                f, "File path {} does not exist.", path.display()
+            ),
+            Error::PermissionDenied { name, pid, uid } => write!(
+                f, "Permission denied: Emacs daemon '{name}' [Pid: {pid}] belongs to {}.",
+                match uid {
+                    Some(uid) => format!("another user (uid {uid})"),
+                    None => "another user".to_string(),
+                }
+            ),
+            Error::StillRunning { name, pid } => write!(
+                f, "Emacs daemon '{name}' [Pid: {pid}] is still running."
+            ),
+            Error::ProcessNotFound { pid } => write!(
+                f, "Error trying to send kill signal to Emacs daemon. No process found with with Pid {pid}."
+            ),
+            Error::SignalFailed { name, pid, .. } => write!(
+                f, "Error trying to send kill signal to Emacs daemon '{name}' with Pid {pid}."
+            ),
+            Error::SignalUnsupported { signal } => write!(
+                f, "Signal::{signal:?} does not exist on this system."
+            ),
+            Error::ClientSpawn(e) => write!(f, "Error launching client:\n{e}"),
+            Error::ClientFailed { status } => write!(f, "Emacs client exited with {status}."),
+            Error::ClientRequest { daemon, message } => write!(
+                f, "Emacs daemon '{daemon}' could not carry out the request: {message}"
+            ),
+            Error::WindowSystemUnsupported { daemon } => write!(
+                f, "Emacs daemon '{daemon}' cannot open a graphical frame; try `--tty`."
+            ),
+            Error::Connection { socket, source } => write!(
+                f, "Error communicating with Emacs daemon at {}: {source}", socket.display()
+            ),
+            Error::Eval { message, .. } => write!(f, "*ERROR*: {message}"),
+            Error::Launch { name, status: Some(status), stderr_tail } => write!(
+                f, "Emacs exited with {status} while launching daemon '{name}':\n{}",
+                stderr_tail.join("\n")
+            ),
+            Error::Launch { name, status: None, stderr_tail } => write!(
+                f, "Emacs daemon '{name}' exited while starting:\n{}",
+                stderr_tail.join("\n")
+            ),
+            Error::NotReady { name, timeout, health } => write!(
+                f, "Emacs daemon '{name}' was not ready after {}s (status: {health}).",
+                timeout.as_secs()
+            ),
+            Error::Unhealthy { name, health } => write!(
+                f, "Emacs daemon '{name}' is not ready (status: {health})."
+            ),
+            Error::Config(e) => write!(f, "{e}"),
+            Error::InvalidArgument(message) => write!(f, "{message}"),
+            Error::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error {
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            Error::DaemonNotFound { .. } => ExitCode::DaemonNotFound,
+            Error::DaemonAlreadyRunning { .. } => ExitCode::DaemonAlreadyRunning,
+            Error::NotReady { .. }
+            | Error::Unhealthy { health: Health::Starting | Health::Unresponsive, .. }
+            | Error::StillRunning { .. } => ExitCode::Timeout,
+            Error::Connection { source, .. } if is_timeout(source) => ExitCode::Timeout,
+            Error::SocketMissing { .. }
+            | Error::ClientSpawn(_)
+            | Error::ClientFailed { .. }
+            | Error::ClientRequest { .. }
+            | Error::WindowSystemUnsupported { .. }
+            | Error::Connection { .. } => ExitCode::ClientFailed,
+            Error::Eval { .. } => ExitCode::EvalFailed,
+            Error::Config(_) | Error::InvalidArgument(_) => ExitCode::Usage,
+            Error::FileNotFound { .. }
+            | Error::PermissionDenied { .. }
+            | Error::ProcessNotFound { .. }
+            | Error::SignalFailed { .. }
+            | Error::SignalUnsupported { .. }
+            | Error::Launch { .. }
+            // i.e. the daemon has no socket, or its socket is unusable
+            | Error::Unhealthy { .. }
+            | Error::Io(_) => ExitCode::Failure,
+        }
+    }
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock)
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        // each message includes that of the error it wraps, so the chain
+        // continues with the wrapped error's own source
+        match self {
+            Error::ClientSpawn(e) | Error::Connection { source: e, .. } | Error::Io(e) => e.source(),
+            Error::Config(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::Config(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn messages_and_sources() {
+        let not_found = Error::DaemonNotFound { name: "work".into(), available: vec![] };
+        assert_eq!(not_found.to_string(), "No Emacs daemons are currently running.");
+        assert!(not_found.source().is_none());
+
+        let refused = Error::Connection {
+            socket: "/tmp/emacs1000/work".into(),
+            source: std::io::Error::from(std::io::ErrorKind::ConnectionRefused),
+        };
+        assert!(refused.to_string().starts_with("Error communicating with Emacs daemon at /tmp/emacs1000/work"));
+        assert!(refused.to_string().ends_with("connection refused"));
+        assert!(refused.source().is_none());
+
+        // no message is repeated by the errors it wraps
+        let inner = Error::Io(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        let io = Error::Io(std::io::Error::other(inner));
+        assert_eq!(io.to_string(), "permission denied");
+        assert!(io.source().is_none());
+    }
+
+    #[test]
+    fn exit_codes() {
+        let timed_out = Error::Connection {
+            socket: "/tmp/emacs1000/work".into(),
+            source: std::io::Error::from(std::io::ErrorKind::WouldBlock),
+        };
+        assert_eq!(timed_out.exit_code() as i32, 5);
+        assert_eq!(Error::DaemonAlreadyRunning { name: "work".into() }.exit_code() as i32, 4);
+        assert_eq!(Error::DaemonNotFound { name: "work".into(), available: vec![] }.exit_code() as i32, 3);
+        let unhealthy = |health| Error::Unhealthy { name: "work".into(), health }.exit_code() as i32;
+        assert_eq!(unhealthy(Health::Unresponsive), 5);
+        assert_eq!(unhealthy(Health::Orphaned), 1);
+        assert_eq!(unhealthy(Health::SocketMissing), 1);
+    }
+}
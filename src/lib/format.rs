@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use clap::ValueEnum;
+use serde::Serialize;
+use sysinfo::PidExt;
+use crate::daemons::DaemonProcess;
+use crate::error::{Error, Result};
+
+/// Machine-readable formats for listing daemons.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Format {
+    /// a JSON array of objects
+    Json,
+    /// one JSON object per line
+    Ndjson,
+    /// tab-separated values
+    Tsv,
+    /// comma-separated values
+    Csv,
+}
+
+/// Every field of a daemon that is listed, in the order listed.
+pub const FIELDS: [&str; 11] = [
+    "name", "pid", "uid", "socket", "start_time", "uptime", "rss", "cpu", "cwd", "exe", "version",
+];
+
+/// The field of a daemon's environment, which is only listed when asked
+/// for (see `eud list --with-env`), as it may well hold secrets.
+pub const ENV_FIELD: &str = "env";
+
+/// A daemon as listed, with plain values for serialisation.
+#[derive(Clone, Debug, Serialize)]
+pub struct DaemonRecord {
+    pub name: String,
+    pub pid: u32,
+    pub uid: Option<u32>,
+    /// the daemon's socket, or its server file if it listens on TCP
+    pub socket: Option<String>,
+    /// seconds since the Unix epoch
+    pub start_time: u64,
+    /// seconds
+    pub uptime: u64,
+    /// resident set size, in bytes
+    pub rss: u64,
+    /// percentage of one CPU, if measured (see `eud list --long`)
+    pub cpu: Option<f32>,
+    pub cwd: Option<String>,
+    pub exe: Option<String>,
+    pub version: Option<String>,
+    /// the daemon's environment, if asked for (empty if unknown, e.g. for
+    /// another user's daemon)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<BTreeMap<String, String>>,
+}
+
+impl From<&DaemonProcess> for DaemonRecord {
+    fn from(daemon: &DaemonProcess) -> Self {
+        Self {
+            name: daemon.socket_name.clone(),
+            pid: daemon.pid.as_u32(),
+            uid: daemon.user_id.as_ref().map(|uid| **uid),
+            socket: daemon.socket_path.as_ref()
+                .or(daemon.server_file.as_ref())
+                .map(|path| path.display().to_string()),
+            start_time: daemon.start_time,
+            uptime: daemon.age().as_secs(),
+            rss: daemon.memory,
+            cpu: daemon.cpu_usage,
+            cwd: daemon.cwd.as_ref().map(|path| path.display().to_string()),
+            exe: daemon.exe.as_ref().map(|path| path.display().to_string()),
+            version: daemon.version.clone(),
+            env: None,
+        }
+    }
+}
+
+impl DaemonRecord {
+    /// This record, including the environment of `daemon`.
+    pub fn with_env(self, daemon: &DaemonProcess) -> Self {
+        let env = daemon.environ
+            .iter()
+            .filter_map(|entry| entry.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Self { env: Some(env), ..self }
+    }
+
+
+    /// The value of `field` (one of `FIELDS`) as text; empty if unknown.
+    pub fn field(&self, field: &str) -> Option<String> {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+        Some(match field {
+            "name" => self.name.clone(),
+            "pid" => self.pid.to_string(),
+            "uid" => self.uid.map(|uid| uid.to_string()).unwrap_or_default(),
+            "socket" => optional(&self.socket),
+            "start_time" => self.start_time.to_string(),
+            "uptime" => self.uptime.to_string(),
+            "rss" => self.rss.to_string(),
+            "cpu" => self.cpu.map(|cpu| format!("{cpu:.1}")).unwrap_or_default(),
+            "cwd" => optional(&self.cwd),
+            "exe" => optional(&self.exe),
+            "version" => optional(&self.version),
+            // as a JSON object, so that any value is unambiguous
+            ENV_FIELD => serde_json::to_string(self.env.as_ref()?).unwrap_or_default(),
+            _ => return None,
+        })
+    }
+}
+
+/// The fields listed, with the environment only if `with_env`.
+pub fn fields(with_env: bool) -> Vec<&'static str> {
+    FIELDS.iter().copied().chain(with_env.then_some(ENV_FIELD)).collect()
+}
+
+/// Render `records` in `format`; `header` includes a row of the names of
+/// `fields` for `Tsv` and `Csv`.
+pub fn render(records: &[DaemonRecord], fields: &[&str], format: Format, header: bool) -> Result<String> {
+    let json_error = |e: serde_json::Error| Error::Io(e.into());
+    Ok(match format {
+        Format::Json => serde_json::to_string_pretty(records).map_err(json_error)? + "\n",
+        Format::Ndjson => records
+            .iter()
+            .map(|record| serde_json::to_string(record).map(|line| line + "\n"))
+            .collect::<std::result::Result<String, _>>()
+            .map_err(json_error)?,
+        Format::Tsv | Format::Csv => {
+            let (separator, escape): (&str, fn(String) -> String) = match format {
+                Format::Tsv => ("\t", tsv_escape),
+                _ => (",", csv_escape),
+            };
+            let rows = records.iter().map(|record| {
+                fields
+                    .iter()
+                    .map(|field| escape(record.field(field).unwrap_or_default()))
+                    .collect::<Vec<String>>()
+                    .join(separator)
+            });
+            header
+                .then(|| fields.join(separator))
+                .into_iter()
+                .chain(rows)
+                .map(|row| row + "\n")
+                .collect()
+        }
+    })
+}
+
+/// Render `record` with `template`, replacing each `{field}` with the
+/// value of that field, and the escapes `\t`, `\n` and `\\`.
+pub fn render_template(template: &str, record: &DaemonRecord) -> Result<String> {
+    let mut rendered = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                let field: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                match record.field(&field) {
+                    Some(value) => rendered.push_str(&value),
+                    None => return Err(Error::InvalidArgument(format!(
+                        "Unknown field `{{{field}}}` in template; fields are: {} (and {ENV_FIELD}, with --with-env)",
+                        FIELDS.join(", ")
+                    ))),
+                }
+            }
+            '\\' => match chars.next() {
+                Some('t') => rendered.push('\t'),
+                Some('n') => rendered.push('\n'),
+                Some(c) => rendered.push(c),
+                None => rendered.push('\\'),
+            },
+            c => rendered.push(c),
+        }
+    }
+    Ok(rendered)
+}
+
+fn tsv_escape(value: String) -> String {
+    value.replace(['\t', '\n'], " ")
+}
+
+fn csv_escape(value: String) -> String {
+    match value.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> DaemonRecord {
+        DaemonRecord {
+            name: "work".into(),
+            pid: 4242,
+            uid: Some(1000),
+            socket: Some("/run/user/1000/emacs/work".into()),
+            start_time: 1_700_000_000,
+            uptime: 60,
+            rss: 123_456,
+            cpu: Some(0.5),
+            cwd: Some("/home/me/my, project".into()),
+            exe: None,
+            version: None,
+            env: None,
+        }
+    }
+
+    fn with_env(record: DaemonRecord) -> DaemonRecord {
+        let env = [("LANG", "C"), ("TERM", "dumb"), ("GREETING", "a b=c")];
+        DaemonRecord {
+            env: Some(env.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()),
+            ..record
+        }
+    }
+
+    #[test]
+    fn renders_separated_values() {
+        assert_eq!(
+            render(&[record()], &fields(false), Format::Csv, true).unwrap(),
+            "name,pid,uid,socket,start_time,uptime,rss,cpu,cwd,exe,version\n\
+             work,4242,1000,/run/user/1000/emacs/work,1700000000,60,123456,0.5,\"/home/me/my, project\",,\n"
+        );
+        assert_eq!(
+            render(&[record()], &fields(false), Format::Tsv, false).unwrap(),
+            "work\t4242\t1000\t/run/user/1000/emacs/work\t1700000000\t60\t123456\t0.5\t/home/me/my, project\t\t\n"
+        );
+        assert_eq!(
+            render(&[with_env(record())], &fields(true), Format::Tsv, false).unwrap().trim_end().rsplit('\t').next(),
+            Some(r#"{"GREETING":"a b=c","LANG":"C","TERM":"dumb"}"#)
+        );
+    }
+
+    #[test]
+    fn renders_json() {
+        let ndjson = render(&[record(), record()], &fields(false), Format::Ndjson, true).unwrap();
+        assert_eq!(ndjson.lines().count(), 2);
+        let value: serde_json::Value = serde_json::from_str(ndjson.lines().next().unwrap()).unwrap();
+        assert_eq!(value["pid"], 4242);
+        assert_eq!(value["version"], serde_json::Value::Null);
+        assert!(value.get("env").is_none());
+
+        let json = render(&[with_env(record())], &fields(true), Format::Json, true).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value[0]["env"]["GREETING"], "a b=c");
+    }
+
+    #[test]
+    fn renders_templates() {
+        assert_eq!(
+            render_template(r"{name}\t{pid}\t{socket}", &record()).unwrap(),
+            "work\t4242\t/run/user/1000/emacs/work"
+        );
+        assert!(render_template("{nmae}", &record()).is_err());
+        assert!(render_template("{env}", &record()).is_err());
+        assert_eq!(render_template("{env}", &with_env(record())).unwrap(), r#"{"GREETING":"a b=c","LANG":"C","TERM":"dumb"}"#);
+    }
+}
@@ -2,3 +2,10 @@ pub mod config;
 pub mod client;
 pub mod daemons;
 pub mod cli;
+pub mod error;
+pub mod format;
+pub mod project;
+#[cfg(test)]
+mod testing;
+
+pub use error::{Error, ExitCode, Result};
@@ -0,0 +1,139 @@
+use std::path::{Path, PathBuf};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Files or directories which mark the root of a project, as recognised
+/// by `project.el` and Projectile: a version-controlled repository, or a
+/// `.projectile` file.
+pub const ROOT_MARKERS: [&str; 6] = [".git", ".hg", ".jj", ".svn", ".bzr", ".projectile"];
+
+/// Files which mark the root of a project outside of any repository (as
+/// within one, they may as well mark a subdirectory of it).
+pub const FALLBACK_ROOT_MARKERS: [&str; 2] = [".dir-locals.el", "flake.nix"];
+
+/// The environment in which a project's daemon is launched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectEnv {
+    /// the environment of `eud` itself
+    #[default]
+    None,
+    /// the project's `nix develop` shell (requires a `flake.nix`)
+    Nix,
+    /// the project's `direnv` environment (requires a `.envrc`)
+    Direnv,
+    /// `nix develop` if the project has a `flake.nix`, else `direnv` if it
+    /// has a `.envrc`, else none
+    Auto,
+}
+
+impl ProjectEnv {
+    /// The command prefix which runs a program in this environment for
+    /// the project at `root` (empty for none).
+    pub fn wrapper(self, root: &Path) -> Vec<String> {
+        let dir = root.display().to_string();
+        match self.resolve(root) {
+            ProjectEnv::Nix => vec!["nix".into(), "develop".into(), dir, "--command".into()],
+            ProjectEnv::Direnv => vec!["direnv".into(), "exec".into(), dir],
+            ProjectEnv::None | ProjectEnv::Auto => vec![],
+        }
+    }
+
+    fn resolve(self, root: &Path) -> Self {
+        match self {
+            ProjectEnv::Auto if root.join("flake.nix").exists() => ProjectEnv::Nix,
+            ProjectEnv::Auto if root.join(".envrc").exists() => ProjectEnv::Direnv,
+            ProjectEnv::Auto => ProjectEnv::None,
+            env => env,
+        }
+    }
+}
+
+/// The root of the project containing `dir`: the nearest directory, from
+/// `dir` upwards, containing any of `ROOT_MARKERS`, or else any of
+/// `FALLBACK_ROOT_MARKERS`.
+pub fn find_root(dir: &Path) -> Option<PathBuf> {
+    let nearest = |markers: &[&str]| {
+        dir.ancestors()
+            .find(|ancestor| markers.iter().any(|marker| ancestor.join(marker).exists()))
+            .map(Path::to_path_buf)
+    };
+    nearest(&ROOT_MARKERS).or_else(|| nearest(&FALLBACK_ROOT_MARKERS))
+}
+
+/// A socket name for the daemon of the project at `root`, which is the
+/// same whenever it is derived from the same path: the name of the
+/// directory, followed by a hash of the whole path (which distinguishes
+/// projects of the same name), e.g. `eud-3f2a9c1b`.
+pub fn socket_name(root: &Path) -> String {
+    let name: String = root
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    let hash = fnv1a(root.as_os_str().as_encoded_bytes());
+    match name.is_empty() {
+        true => format!("project-{:08x}", hash as u32),
+        false => format!("{name}-{:08x}", hash as u32),
+    }
+}
+
+/// The 64-bit FNV-1a hash of `bytes`, which (unlike `DefaultHasher`) is
+/// stable between builds.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::ScratchDir;
+
+    #[test]
+    fn finds_nearest_root() {
+        let dir = ScratchDir::new("project-root");
+        let repo = dir.join("repo");
+        let nested = repo.join("src").join("lib");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        std::fs::create_dir_all(&nested).unwrap();
+
+        assert_eq!(find_root(&nested), Some(repo.clone()));
+        // a nested `.dir-locals.el` or `flake.nix` does not split a repository
+        std::fs::write(repo.join("src").join("flake.nix"), "").unwrap();
+        std::fs::write(nested.join(".dir-locals.el"), "").unwrap();
+        assert_eq!(find_root(&nested), Some(repo.clone()));
+        // .. but marks a project outside of one
+        std::fs::remove_dir_all(repo.join(".git")).unwrap();
+        assert_eq!(find_root(&nested), Some(nested.clone()));
+        assert_eq!(find_root(&repo.join("src")), Some(repo.join("src")));
+    }
+
+    #[test]
+    fn stable_socket_names() {
+        let name = socket_name(Path::new("/home/me/src/my project"));
+        assert_eq!(name, socket_name(Path::new("/home/me/src/my project")));
+        assert!(name.starts_with("my_project-"));
+        assert_eq!(name.len(), "my_project-".len() + 8);
+        assert_ne!(name, socket_name(Path::new("/home/me/work/my project")));
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn environment_wrappers() {
+        let dir = ScratchDir::new("project-env");
+        assert!(ProjectEnv::Auto.wrapper(&dir).is_empty());
+        std::fs::write(dir.join(".envrc"), "use nix\n").unwrap();
+        assert_eq!(ProjectEnv::Auto.wrapper(&dir)[..2], ["direnv", "exec"]);
+        std::fs::write(dir.join("flake.nix"), "").unwrap();
+        assert_eq!(ProjectEnv::Auto.wrapper(&dir).last().unwrap(), "--command");
+        assert!(ProjectEnv::None.wrapper(&dir).is_empty());
+    }
+}
@@ -0,0 +1,60 @@
+// Helpers shared by the tests of each module.
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use crate::config::{Config, Overrides};
+
+
+/// A directory for a test's files, which is removed (with everything in
+/// it) when dropped, whether or not the test passes.
+pub(crate) struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    /// A fresh, empty directory named for this process and `name` (which
+    /// should be unique to the test).
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("eud-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for ScratchDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+
+/// A configuration read only from `settings` (as TOML), with its socket
+/// and server directories within `dir`: neither the user's configuration
+/// file nor any `EUD_*` environment variable is read.
+pub(crate) fn config(dir: &Path, settings: &str) -> Config {
+    let file = dir.join("config.toml");
+    fs::write(&file, format!(
+        concat!(
+            "server_socket_dir = \"{0}/sockets\"\n",
+            "server_auth_dir = \"{0}/server\"\n",
+            "{1}",
+        ),
+        dir.display(),
+        settings,
+    )).unwrap();
+    Config::load_with_env(Some(&file), &Overrides::default(), Some(HashMap::new())).unwrap()
+}
@@ -1,9 +1,11 @@
-use eud::config::Config;
 use eud::cli::cli;
 
 fn main() {
-    match cli(&Config::default()) {
+    match cli() {
         Ok(_) => (),
-        Err(e) => eprint!("{}", e),
+        Err(e) => {
+            eprintln!("{}", e.to_string().trim_end());
+            std::process::exit(e.exit_code() as i32);
+        }
     }
 }
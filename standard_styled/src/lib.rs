@@ -1,8 +1,10 @@
 // Derived from https://stackoverflow.com/a/55565595
 use std::{
+    collections::VecDeque,
     io::Write,
     ops::Deref,
-    process::Stdio,
+    path::PathBuf,
+    process::{ExitStatus, Stdio},
     sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex},
 };
 use tokio::{
@@ -18,6 +20,13 @@ pub struct CommandParts
 {
     pub program: String,
     pub args: Vec<String>,
+    /// working directory of the command, if not the current one
+    pub cwd: Option<PathBuf>,
+    /// environment variables set for the command
+    pub env: Vec<(String, String)>,
+    /// whether the command starts with an empty environment (i.e. only
+    /// `env`), rather than inheriting this process's
+    pub clear_env: bool,
 }
 
 impl CommandParts {
@@ -29,6 +38,9 @@ impl CommandParts {
         Self {
             program: program.into(),
             args: args.into(),
+            cwd: None,
+            env: Vec::new(),
+            clear_env: false,
         }
     }
 
@@ -39,6 +51,13 @@ impl CommandParts {
     pub fn build(&self) -> Command {
         let mut cmd = Command::new(&self.program);
         cmd.args(self.args());
+        if self.clear_env {
+            cmd.env_clear();
+        }
+        cmd.envs(self.env.iter().cloned());
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
         cmd
     }
 }
@@ -50,6 +69,7 @@ pub struct Style {
     pub stderr_style: Box<dyn Fn(&str) -> ColoredString>,
     pub message_style: Box<dyn Fn(&str) -> ColoredString>,
     pub end_message: Option<String>,
+    pub failure_message: Option<String>,
 }
 
 impl Style {
@@ -59,6 +79,7 @@ impl Style {
         stderr_style: Box<dyn Fn(&str) -> ColoredString>,
         message_style: Box<dyn Fn(&str) -> ColoredString>,
         end_message: Option<String>,
+        failure_message: Option<String>,
     ) -> Self {
         Self {
             spinner,
@@ -66,6 +87,17 @@ impl Style {
             stderr_style,
             message_style,
             end_message,
+            failure_message,
+        }
+    }
+
+    /// Show the ending message for a successful outcome, or the failure
+    /// message otherwise (if there is one).
+    pub fn finish(&self, success: bool) {
+        match (success, &self.end_message, &self.failure_message) {
+            (true, Some(message), _) => println!("{}", (self.message_style)(message)),
+            (false, _, Some(message)) => eprintln!("{}", (self.stderr_style)(message)),
+            _ => (),
         }
     }
 
@@ -125,16 +157,6 @@ impl<'s> View<'s> {
         std::io::stdout().flush().expect("cannot flush stdout");
     }
 
-    fn show_end_message(&self) {
-        match &self.style.end_message {
-            None => (),
-            Some(message) => {
-                self.clear();
-                println!("{}", (self.style.message_style)(message) );
-            }
-        }
-    }
-
     fn tick(&self) -> usize {
         self.ticker.fetch_add(1, Ordering::SeqCst)
     }
@@ -151,10 +173,31 @@ impl<'s> View<'s> {
     }
 }
 
+/// The number of lines from the end of a command's stderr kept in
+/// `Completion::stderr_tail`.
+const STDERR_TAIL: usize = 10;
+
+/// How a command run by `standard_styled` completed.
+#[derive(Debug)]
+pub struct Completion {
+    pub status: ExitStatus,
+    /// the last lines written by the command to stderr
+    pub stderr_tail: Vec<String>,
+}
+
+impl Completion {
+    pub fn success(&self) -> bool {
+        self.status.success()
+    }
+}
+
+/// Run `command`, showing a spinner alongside each line of its output as
+/// it is written, until it exits. The spinner is cleared on completion,
+/// leaving any ending message to `Style::finish`.
 pub async fn standard_styled(
     command: CommandParts,
     style: &Style
-) -> std::io::Result<()> {
+) -> std::io::Result<Completion> {
 
     let mut child = command.build()
         .stdout(Stdio::piped())
@@ -170,9 +213,11 @@ pub async fn standard_styled(
     let mut view = View::with_style(style);
     view.print();
 
-    loop {
+    let mut stderr_tail = VecDeque::with_capacity(STDERR_TAIL);
+    let (mut stdout_open, mut stderr_open) = (true, true);
+    while stdout_open || stderr_open {
         tokio::select! {
-            result = stdout_reader.next_line() => {
+            result = stdout_reader.next_line(), if stdout_open => {
                 match result {
                     Ok(Some(line)) => {
                         let msg = Message::StdOut(line);
@@ -180,36 +225,41 @@ pub async fn standard_styled(
                         view.tick();
                         view.print();
                     }
-                    Ok(None) => break,
-                    Err(_) => (),
+                    Ok(None) | Err(_) => stdout_open = false,
                 }
             },
-            result = stderr_reader.next_line() => {
+            result = stderr_reader.next_line(), if stderr_open => {
                 match result {
                     Ok(Some(line)) => {
+                        if stderr_tail.len() == STDERR_TAIL {
+                            stderr_tail.pop_front();
+                        }
+                        stderr_tail.push_back(line.clone());
                         let msg = Message::StdErr(line);
                         view.update_message(&msg);
                         view.tick();
                         view.print();
                     }
-                    Err(_) => (),
-                    _ => (),
+                    Ok(None) | Err(_) => stderr_open = false,
                 }
             }
         }
     }
 
-    // command has completed, play ending message (if there is one)
-    view.show_end_message();
+    let status = child.wait().await?;
+    view.clear();
 
-    Ok(())
+    Ok(Completion {
+        status,
+        stderr_tail: stderr_tail.into(),
+    })
 }
 
 
 #[cfg(test)]
 mod tests {
     use std::ffi::OsStr;
-    use crate::CommandParts;
+    use crate::{standard_styled, Colorize, CommandParts, Style};
 
     #[test]
     fn command_parts_build_std_command() {
@@ -229,6 +279,20 @@ mod tests {
     }
 
 
+    #[tokio::test]
+    async fn command_parts_cwd_and_env() {
+        let mut cmd = CommandParts::new(
+            &"sh".to_string(),
+            &["-c".to_string(), "echo $PWD $EUD_TEST ${HOME:-none}".to_string()],
+        );
+        cmd.cwd = Some("/".into());
+        cmd.env = vec![("EUD_TEST".to_string(), "abc".to_string())];
+        cmd.clear_env = true;
+        let output = cmd.build().output().await.unwrap();
+
+        assert_eq!(output.stdout, "/ abc none\n".as_bytes());
+    }
+
     #[tokio::test]
     async fn test_stdout_out_only() {
         let mut echo = CommandParts::new(
@@ -252,4 +316,26 @@ mod tests {
 
         assert_eq!(output.stderr, "abc\n".as_bytes());
     }
+
+    #[tokio::test]
+    async fn test_completion_keeps_status_and_stderr_tail() {
+        let style = Style::new(
+            vec!["."],
+            Box::new(|s: &str| s.normal()),
+            Box::new(|s: &str| s.normal()),
+            Box::new(|s: &str| s.normal()),
+            None,
+            None,
+        );
+        let failing = CommandParts::new(
+            &"bash".to_string(),
+            &["-c".to_string(), "for i in $(seq 1 12); do echo $i >&2; done; exit 3".to_string()],
+        );
+        let completion = standard_styled(failing, &style).await.unwrap();
+
+        assert!(!completion.success());
+        assert_eq!(completion.status.code(), Some(3));
+        assert_eq!(completion.stderr_tail.first().map(String::as_str), Some("3"));
+        assert_eq!(completion.stderr_tail.last().map(String::as_str), Some("12"));
+    }
 }